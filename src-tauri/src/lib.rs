@@ -2,7 +2,7 @@ use std::{
     collections::HashMap,
     fs,
     io::{BufWriter, Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use base64::prelude::*;
@@ -22,6 +22,18 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             lyrics::get_lyrics,
+            spotify::analysis::get_track_key,
+            spotify::analysis::get_clip_totals,
+            spotify::analysis::get_track_overview,
+            spotify::analysis::get_track_structure,
+            spotify::feed::subscribe_audio_feed,
+            spotify::feed::unsubscribe_audio_feed,
+            spotify::restart_audio_pipeline,
+            spotify::devices::list_output_devices,
+            spotify::devices::set_output_device,
+            spotify::devices::switch_output_device,
+            spotify::devices::set_output_latency_offset,
+            spotify::stats::get_audio_pipeline_stats,
             upload_logo,
             store_string,
             read_string
@@ -39,6 +51,10 @@ pub fn run() {
             app.manage(AppConfigState {
                 app_dir: path.clone(),
             });
+            app.manage(spotify::analysis::AnalysisStore::default());
+            app.manage(spotify::feed::AudioFeed::default());
+            app.manage(spotify::SpotifyHandle::default());
+            app.manage(spotify::stats::PipelineStatsStore::default());
 
             let mut speaker_name = read_config(&path, "name".to_string()).unwrap();
             if speaker_name.is_none() {
@@ -52,15 +68,14 @@ pub fn run() {
             let speaker_name = speaker_name.unwrap();
 
             let handler_clone = app.handle().clone();
-            app.once("start_listen", move |_event| {
-                let handler_clone = handler_clone.to_owned();
-                let boxed_handle = Box::new(handler_clone);
-                info!("Starting Spotify setup as speaker: {}", speaker_name);
-                tauri::async_runtime::spawn(async move {
-                    spotify::setup(boxed_handle, speaker_name.as_str())
-                        .await
-                        .unwrap();
-                });
+            let spotify_app_dir = path.clone();
+            // Sent again by a reloaded webview, which retries a failed setup
+            app.listen("start_listen", move |_event| {
+                spotify::start(
+                    handler_clone.clone(),
+                    speaker_name.clone(),
+                    spotify_app_dir.clone(),
+                );
             });
 
             Ok(())
@@ -78,7 +93,7 @@ struct UploadResult {
 
 #[derive(Default)]
 struct AppConfigState {
-    app_dir: PathBuf, 
+    app_dir: PathBuf,
 }
 #[tauri::command]
 async fn upload_logo(
@@ -90,7 +105,7 @@ async fn upload_logo(
 
     // Create the logo directory if it doesn't exist
     let logo_dir = app_dir.join("logos");
-    fs::create_dir_all(&logo_dir).map_err(|e| tauri::Error::Io(e))?;
+    fs::create_dir_all(&logo_dir).map_err(tauri::Error::Io)?;
 
     let file_path = logo_dir.join(&filename);
 
@@ -99,9 +114,8 @@ async fn upload_logo(
         .map_err(|e| tauri::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
 
     // Write the file to disk
-    let mut file = fs::File::create(&file_path).map_err(|e| tauri::Error::Io(e))?;
-    file.write_all(&decoded_data)
-        .map_err(|e| tauri::Error::Io(e))?;
+    let mut file = fs::File::create(&file_path).map_err(tauri::Error::Io)?;
+    file.write_all(&decoded_data).map_err(tauri::Error::Io)?;
 
     let data_file_path = app_dir.join("data.txt");
     write_config(&data_file_path, "logo".to_string(), filename)?;
@@ -138,14 +152,14 @@ async fn read_string(
     return read_config(app_dir, key);
 }
 
-fn write_config(data_file_path: &PathBuf, key: String, value: String) -> Result<(), tauri::Error> {
+fn write_config(data_file_path: &Path, key: String, value: String) -> Result<(), tauri::Error> {
     let mut data: HashMap<String, String> = HashMap::new();
 
     if data_file_path.exists() {
-        let mut file = fs::File::open(&data_file_path).map_err(|e| tauri::Error::Io(e))?;
+        let mut file = fs::File::open(data_file_path).map_err(tauri::Error::Io)?;
         let mut content = String::new();
         file.read_to_string(&mut content)
-            .map_err(|e| tauri::Error::Io(e))?;
+            .map_err(tauri::Error::Io)?;
 
         for line in content.lines() {
             if let Some((k, v)) = line.split_once("=") {
@@ -156,32 +170,31 @@ fn write_config(data_file_path: &PathBuf, key: String, value: String) -> Result<
 
     data.insert(key, value);
 
-    let file = fs::File::create(&data_file_path).map_err(|e| tauri::Error::Io(e))?;
+    let file = fs::File::create(data_file_path).map_err(tauri::Error::Io)?;
     let mut writer = BufWriter::new(file);
 
     for (k, v) in data.iter() {
         let line = format!("{}={}\n", k, v);
         writer
             .write_all(line.as_bytes())
-            .map_err(|e| tauri::Error::Io(e))?;
+            .map_err(tauri::Error::Io)?;
     }
 
-    writer.flush().map_err(|e| tauri::Error::Io(e))?;
+    writer.flush().map_err(tauri::Error::Io)?;
 
     Ok(())
 }
 
-fn read_config(app_dir: &PathBuf, key: String) -> Result<Option<String>, tauri::Error> {
+fn read_config(app_dir: &Path, key: String) -> Result<Option<String>, tauri::Error> {
     let data_file_path = app_dir.join("data.txt");
 
     if !data_file_path.exists() {
         return Ok(None); // File doesn't exist, return None
     }
 
-    let mut file = fs::File::open(&data_file_path).map_err(|e| tauri::Error::Io(e))?;
+    let mut file = fs::File::open(data_file_path).map_err(tauri::Error::Io)?;
     let mut data = String::new();
-    file.read_to_string(&mut data)
-        .map_err(|e| tauri::Error::Io(e))?;
+    file.read_to_string(&mut data).map_err(tauri::Error::Io)?;
 
     // Search for the key=value pair
     for line in data.lines() {
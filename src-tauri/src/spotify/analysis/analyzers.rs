@@ -0,0 +1,332 @@
+use tauri::Manager;
+
+use super::{
+    beat::{BeatOutput, BeatTracker},
+    clipping::{ClipReporter, ClipTotals},
+    descriptors::DescriptorAnalyzer,
+    key::KeyDetector,
+    loudness::LoudnessMeter,
+    overview::OverviewBuilder,
+    pipeline::{Analyzer, AnalyzerInput, EventSink, SampleBlock},
+    pitch::PitchTracker,
+    silence::{SilenceDetector, SilenceOutput},
+    spectrum::SpectrumAnalyzer,
+    stereo::StereoAnalyzer,
+    structure::StructureAnalyzer,
+    AnalysisControl, AnalysisStore,
+};
+
+const SPECTRUM_EVENT: &str = "audio_spectrum";
+const DESCRIPTORS_EVENT: &str = "spectral_descriptors";
+const ONSET_EVENT: &str = "onset";
+const BEAT_EVENT: &str = "beat";
+const LOUDNESS_EVENT: &str = "loudness";
+const TRACK_KEY_EVENT: &str = "track_key";
+const STEREO_FIELD_EVENT: &str = "stereo_field";
+const SILENCE_STARTED_EVENT: &str = "silence_started";
+const SILENCE_ENDED_EVENT: &str = "silence_ended";
+const CLIPPING_REPORT_EVENT: &str = "clipping_report";
+const TRACK_OVERVIEW_EVENT: &str = "track_overview";
+const PITCH_EVENT: &str = "pitch";
+const SECTION_CHANGED_EVENT: &str = "section_changed";
+const TRACK_STRUCTURE_EVENT: &str = "track_structure";
+
+/// Reports the sink's clip counters once per second of audio and keeps the
+/// per-track totals in the `AnalysisStore`. It goes by the counter snapshots
+/// in the blocks, which line up with the track changes.
+pub struct ClippingAnalyzer {
+    reporter: ClipReporter,
+    /// Snapshot taken with the latest block.
+    played: ClipTotals,
+    frames_since_report: usize,
+    track_id: Option<String>,
+}
+
+impl ClippingAnalyzer {
+    pub fn new(start: ClipTotals) -> Self {
+        Self {
+            reporter: ClipReporter::new(start),
+            played: start,
+            frames_since_report: 0,
+            track_id: None,
+        }
+    }
+
+    fn report(&mut self, sink: &EventSink) {
+        let (delta, report) = self.reporter.report(self.played);
+
+        if let Some(track_id) = &self.track_id {
+            let store = sink.app_handle().state::<AnalysisStore>();
+            store
+                .clips
+                .lock()
+                .unwrap()
+                .entry(track_id.clone())
+                .or_default()
+                .add(&delta);
+        }
+
+        sink.emit(CLIPPING_REPORT_EVENT, report);
+    }
+}
+
+impl Analyzer for ClippingAnalyzer {
+    fn name(&self) -> &'static str {
+        "clipping"
+    }
+
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Captured
+    }
+
+    fn skippable(&self) -> bool {
+        false
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        self.played = block.clips;
+        self.frames_since_report += block.frames();
+        if self.frames_since_report >= block.sample_rate as usize {
+            self.frames_since_report = 0;
+            self.report(sink);
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, sink: &EventSink) {
+        if let AnalysisControl::TrackChanged { track_id, .. } = control {
+            // Settle the previous track's clip counts before switching
+            self.report(sink);
+            self.reporter.start_track();
+            self.track_id = Some(track_id.clone());
+        }
+    }
+}
+
+impl Analyzer for LoudnessMeter {
+    fn name(&self) -> &'static str {
+        "loudness"
+    }
+
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Captured
+    }
+
+    fn skippable(&self) -> bool {
+        false
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for report in self.process(&block.samples) {
+            sink.emit(LOUDNESS_EVENT, report);
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, _sink: &EventSink) {
+        if let AnalysisControl::TrackChanged { .. } = control {
+            self.reset_integrated();
+        }
+    }
+}
+
+impl Analyzer for SilenceDetector {
+    fn name(&self) -> &'static str {
+        "silence"
+    }
+
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Captured
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for output in self.process(&block.samples) {
+            match output {
+                SilenceOutput::Started(event) => sink.emit(SILENCE_STARTED_EVENT, event),
+                SilenceOutput::Ended(event) => sink.emit(SILENCE_ENDED_EVENT, event),
+            }
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, _sink: &EventSink) {
+        if let AnalysisControl::Paused | AnalysisControl::Stopped = control {
+            self.interrupt();
+        }
+    }
+}
+
+impl Analyzer for OverviewBuilder {
+    fn name(&self) -> &'static str {
+        "overview"
+    }
+
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Captured
+    }
+
+    fn skippable(&self) -> bool {
+        false
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, _sink: &EventSink) {
+        self.process(&block.samples, block.position_ms);
+    }
+
+    fn control(&mut self, control: &AnalysisControl, sink: &EventSink) {
+        match control {
+            AnalysisControl::TrackChanged {
+                track_id,
+                duration_ms,
+            } => {
+                if let Some(stored) = self.start_track(track_id.clone(), *duration_ms) {
+                    sink.emit(TRACK_OVERVIEW_EVENT, stored);
+                }
+            }
+            AnalysisControl::Seeked { .. } => self.seeked(),
+            AnalysisControl::Stopped => self.save(),
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, _sink: &EventSink) {
+        self.save();
+    }
+}
+
+impl Analyzer for StructureAnalyzer {
+    fn name(&self) -> &'static str {
+        "structure"
+    }
+
+    /// Normalization would flatten the energy differences between sections.
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Captured
+    }
+
+    fn skippable(&self) -> bool {
+        false
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        if let Some(change) = self.process(&block.samples, block.position_ms) {
+            sink.emit(SECTION_CHANGED_EVENT, change);
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, sink: &EventSink) {
+        match control {
+            AnalysisControl::TrackChanged {
+                track_id,
+                duration_ms,
+            } => {
+                if let Some(stored) = self.start_track(track_id.clone(), *duration_ms) {
+                    sink.emit(TRACK_STRUCTURE_EVENT, stored);
+                }
+            }
+            AnalysisControl::Seeked { .. } => self.seeked(),
+            AnalysisControl::Stopped => self.save(),
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, _sink: &EventSink) {
+        self.save();
+    }
+}
+
+impl Analyzer for SpectrumAnalyzer {
+    fn name(&self) -> &'static str {
+        "spectrum"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for frame in self.process(&block.samples) {
+            sink.emit(SPECTRUM_EVENT, frame);
+        }
+    }
+}
+
+impl Analyzer for DescriptorAnalyzer {
+    fn name(&self) -> &'static str {
+        "descriptors"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for descriptors in self.process(&block.samples) {
+            sink.emit(DESCRIPTORS_EVENT, descriptors);
+        }
+    }
+}
+
+impl Analyzer for BeatTracker {
+    fn name(&self) -> &'static str {
+        "beat"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for output in self.process(&block.samples) {
+            match output {
+                BeatOutput::Onset(onset) => sink.emit(ONSET_EVENT, onset),
+                BeatOutput::Beat(beat) => sink.emit(BEAT_EVENT, beat),
+            }
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, _sink: &EventSink) {
+        if let AnalysisControl::TrackChanged { .. } = control {
+            self.reset();
+        }
+    }
+}
+
+impl Analyzer for KeyDetector {
+    fn name(&self) -> &'static str {
+        "key"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        if let Some(estimate) = self.process(&block.samples) {
+            let store = sink.app_handle().state::<AnalysisStore>();
+            store
+                .keys
+                .lock()
+                .unwrap()
+                .insert(estimate.track_id.clone(), estimate.clone());
+            sink.emit(TRACK_KEY_EVENT, estimate);
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, _sink: &EventSink) {
+        if let AnalysisControl::TrackChanged { track_id, .. } = control {
+            self.start_track(track_id.clone());
+        }
+    }
+}
+
+impl Analyzer for StereoAnalyzer {
+    fn name(&self) -> &'static str {
+        "stereo"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for field in self.process(&block.samples) {
+            sink.emit(STEREO_FIELD_EVENT, field);
+        }
+    }
+}
+
+impl Analyzer for PitchTracker {
+    fn name(&self) -> &'static str {
+        "pitch"
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink) {
+        for frame in self.process(&block.samples, block.position_ms) {
+            sink.emit(PITCH_EVENT, frame);
+        }
+    }
+
+    fn control(&mut self, control: &AnalysisControl, _sink: &EventSink) {
+        if let AnalysisControl::TrackChanged { .. } | AnalysisControl::Seeked { .. } = control {
+            self.reset();
+        }
+    }
+}
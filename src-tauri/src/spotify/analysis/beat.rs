@@ -0,0 +1,329 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+
+const FFT_SIZE: usize = 1024;
+const HOP_SIZE: usize = 512;
+
+/// Seconds of onset strength history used for tempo estimation.
+const TEMPO_HISTORY_SECS: f32 = 8.0;
+/// Minimum history before a tempo estimate is attempted.
+const TEMPO_MIN_HISTORY_SECS: f32 = 3.0;
+/// Seconds of history used for the adaptive onset threshold.
+const THRESHOLD_WINDOW_SECS: f32 = 1.0;
+
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 180.0;
+/// Tempo prior: a log-normal weighting centred on this tempo.
+const PREFERRED_BPM: f32 = 120.0;
+
+/// How far (as a fraction of the beat period) an onset may be from the
+/// predicted beat and still be taken as that beat.
+const BEAT_TOLERANCE: f32 = 0.2;
+
+/// Payload of the `onset` event.
+#[derive(Serialize, Clone)]
+pub struct OnsetEvent {
+    /// Onset strength relative to the adaptive threshold, >= 1.0.
+    pub strength: f32,
+}
+
+/// Payload of the `beat` event.
+#[derive(Serialize, Clone)]
+pub struct BeatEvent {
+    pub bpm: f32,
+    /// 0-1, combining how periodic the recent onsets are and whether this
+    /// beat landed on an actual onset or was only predicted.
+    pub confidence: f32,
+    /// Position within the current beat period (0-1) at the moment the event
+    /// was emitted. Non-zero when the beat was confirmed late, so consumers
+    /// can shorten the matching animation.
+    pub phase: f32,
+}
+
+pub enum BeatOutput {
+    Onset(OnsetEvent),
+    Beat(BeatEvent),
+}
+
+/// Spectral flux onset detection followed by an autocorrelation tempo
+/// estimate and a simple predictive beat tracker.
+pub struct BeatTracker {
+    channels: usize,
+    frame_rate: f32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    pending: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    prev_spectrum: Vec<f32>,
+
+    /// Onset strength function, one value per hop.
+    odf: VecDeque<f32>,
+    /// Index of the most recent hop.
+    frame: u64,
+
+    /// Beat period in hops, once a tempo has been found.
+    period: Option<f32>,
+    tempo_confidence: f32,
+    last_beat: Option<f32>,
+    pending_onset: Option<f32>,
+}
+
+impl BeatTracker {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        Self {
+            channels: channels.max(1),
+            frame_rate: sample_rate as f32 / HOP_SIZE as f32,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            pending: Vec::with_capacity(FFT_SIZE * 2),
+            scratch: vec![Complex::new(0.0, 0.0); FFT_SIZE],
+            prev_spectrum: vec![0.0; FFT_SIZE / 2 + 1],
+            odf: VecDeque::new(),
+            frame: 0,
+            period: None,
+            tempo_confidence: 0.0,
+            last_beat: None,
+            pending_onset: None,
+        }
+    }
+
+    /// Forgets all history, e.g. when a new track starts.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.prev_spectrum.iter_mut().for_each(|v| *v = 0.0);
+        self.odf.clear();
+        self.frame = 0;
+        self.period = None;
+        self.tempo_confidence = 0.0;
+        self.last_beat = None;
+        self.pending_onset = None;
+    }
+
+    /// Feeds interleaved samples and returns the onsets and beats found.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<BeatOutput> {
+        self.pending.extend(
+            samples
+                .chunks_exact(self.channels)
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32),
+        );
+
+        let mut outputs = Vec::new();
+        while self.pending.len() >= FFT_SIZE {
+            let flux = self.spectral_flux();
+            self.pending.drain(..HOP_SIZE);
+            self.push_odf(flux, &mut outputs);
+        }
+        outputs
+    }
+
+    fn spectral_flux(&mut self) -> f32 {
+        for ((out, sample), w) in self.scratch.iter_mut().zip(&self.pending).zip(&self.window) {
+            *out = Complex::new(sample * w, 0.0);
+        }
+        self.fft.process(&mut self.scratch);
+
+        let mut flux = 0.0;
+        for (prev, bin) in self.prev_spectrum.iter_mut().zip(&self.scratch) {
+            // Log compression keeps the flux from being dominated by loud bass
+            let magnitude = (1.0 + 100.0 * bin.norm()).ln();
+            flux += (magnitude - *prev).max(0.0);
+            *prev = magnitude;
+        }
+        flux
+    }
+
+    fn push_odf(&mut self, flux: f32, outputs: &mut Vec<BeatOutput>) {
+        self.frame += 1;
+        self.odf.push_back(flux);
+        let max_history = (TEMPO_HISTORY_SECS * self.frame_rate) as usize;
+        while self.odf.len() > max_history {
+            self.odf.pop_front();
+        }
+
+        if let Some((onset_frame, strength)) = self.detect_onset() {
+            outputs.push(BeatOutput::Onset(OnsetEvent { strength }));
+            self.pending_onset = Some(onset_frame);
+        }
+
+        if self.frame % self.frame_rate as u64 == 0 {
+            self.estimate_tempo();
+        }
+
+        if let Some(beat) = self.track_beat() {
+            outputs.push(BeatOutput::Beat(beat));
+        }
+    }
+
+    /// Peak picking against an adaptive mean + deviation threshold. A peak is
+    /// confirmed one hop late, once the following value is known.
+    fn detect_onset(&self) -> Option<(f32, f32)> {
+        let n = self.odf.len();
+        if n < 3 {
+            return None;
+        }
+        let (before, peak, after) = (self.odf[n - 3], self.odf[n - 2], self.odf[n - 1]);
+        if peak <= before || peak < after {
+            return None;
+        }
+
+        let window = ((THRESHOLD_WINDOW_SECS * self.frame_rate) as usize).min(n);
+        let recent = self.odf.iter().skip(n - window);
+        let mean = recent.clone().sum::<f32>() / window as f32;
+        let deviation = (recent.map(|v| (v - mean).powi(2)).sum::<f32>() / window as f32).sqrt();
+        let threshold = mean + 1.5 * deviation + 1e-3;
+
+        (peak > threshold).then(|| ((self.frame - 1) as f32, peak / threshold))
+    }
+
+    /// Picks the beat period with the strongest (tempo-weighted)
+    /// autocorrelation of the onset strength function.
+    fn estimate_tempo(&mut self) {
+        let n = self.odf.len();
+        if (n as f32) < TEMPO_MIN_HISTORY_SECS * self.frame_rate {
+            return;
+        }
+
+        let mean = self.odf.iter().sum::<f32>() / n as f32;
+        let odf: Vec<f32> = self.odf.iter().map(|v| v - mean).collect();
+        let energy = odf.iter().map(|v| v * v).sum::<f32>();
+        if energy <= f32::EPSILON {
+            return;
+        }
+
+        let min_lag = (60.0 / MAX_BPM * self.frame_rate).floor() as usize;
+        let max_lag = ((60.0 / MIN_BPM * self.frame_rate).ceil() as usize).min(n / 2);
+        let acf = |lag: usize| -> f32 {
+            if lag >= n {
+                return 0.0;
+            }
+            odf.iter().zip(&odf[lag..]).map(|(a, b)| a * b).sum::<f32>() / energy
+        };
+
+        let scores: Vec<f32> = (min_lag..=max_lag)
+            .map(|lag| {
+                let bpm = 60.0 * self.frame_rate / lag as f32;
+                let octaves = (bpm / PREFERRED_BPM).log2();
+                let prior = (-0.5 * octaves * octaves).exp();
+                (acf(lag) + 0.5 * acf(lag * 2)) * prior
+            })
+            .collect();
+
+        let Some((best, &score)) = scores.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))
+        else {
+            return;
+        };
+        if score <= 0.0 {
+            return;
+        }
+
+        // Parabolic interpolation around the peak for sub-hop precision
+        let mut lag = (min_lag + best) as f32;
+        if best > 0 && best + 1 < scores.len() {
+            let (a, b, c) = (scores[best - 1], scores[best], scores[best + 1]);
+            let denominator = a - 2.0 * b + c;
+            if denominator.abs() > f32::EPSILON {
+                lag += (0.5 * (a - c) / denominator).clamp(-0.5, 0.5);
+            }
+        }
+
+        let mean_score = scores.iter().sum::<f32>() / scores.len() as f32;
+        let confidence = ((score - mean_score) / score).clamp(0.0, 1.0);
+
+        self.period = Some(match self.period {
+            // Smooth small drifts, but jump straight to a clearly different tempo
+            Some(period) if (lag / period - 1.0).abs() < 0.04 => 0.8 * period + 0.2 * lag,
+            Some(period) if confidence < self.tempo_confidence * 0.8 => period,
+            _ => lag,
+        });
+        self.tempo_confidence = 0.7 * self.tempo_confidence + 0.3 * confidence;
+    }
+
+    fn track_beat(&mut self) -> Option<BeatEvent> {
+        let period = self.period?;
+        let now = self.frame as f32;
+        let onset = self.pending_onset.take();
+
+        let Some(last_beat) = self.last_beat else {
+            // Anchor the beat grid on the first onset after a tempo is known
+            return onset.map(|onset_frame| self.beat_at(onset_frame, now, period, 1.0));
+        };
+
+        let expected = last_beat + period;
+        let tolerance = BEAT_TOLERANCE * period;
+
+        if let Some(onset_frame) = onset {
+            if (onset_frame - expected).abs() <= tolerance {
+                return Some(self.beat_at(onset_frame, now, period, 1.0));
+            }
+        }
+
+        // No onset close enough to the prediction, keep the grid going
+        (now > expected + tolerance).then(|| self.beat_at(expected, now, period, 0.5))
+    }
+
+    fn beat_at(&mut self, beat_frame: f32, now: f32, period: f32, weight: f32) -> BeatEvent {
+        self.last_beat = Some(beat_frame);
+        BeatEvent {
+            bpm: 60.0 * self.frame_rate / period,
+            confidence: (self.tempo_confidence * weight).clamp(0.0, 1.0),
+            phase: ((now - beat_frame) / period).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Interleaved stereo click track: a short decaying 2 kHz burst on
+    /// every beat.
+    fn click_track(bpm: f32, seconds: f32) -> Vec<f32> {
+        let frames = (seconds * SAMPLE_RATE as f32) as usize;
+        let period = (60.0 / bpm * SAMPLE_RATE as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = (i % period) as f32 / SAMPLE_RATE as f32;
+                let s = (2.0 * std::f32::consts::PI * 2000.0 * t).sin() * (-t * 200.0).exp();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn click_track_reports_its_tempo() {
+        let mut tracker = BeatTracker::new(SAMPLE_RATE, 2);
+        let outputs: Vec<_> = click_track(120.0, 10.0)
+            .chunks(2048)
+            .flat_map(|c| tracker.process(c))
+            .collect();
+
+        let onsets = outputs
+            .iter()
+            .filter(|o| matches!(o, BeatOutput::Onset(_)))
+            .count();
+        // 20 clicks, allowing for the first few while the threshold settles
+        assert!((17..=20).contains(&onsets), "{} onsets", onsets);
+
+        let beats: Vec<_> = outputs
+            .iter()
+            .filter_map(|o| match o {
+                BeatOutput::Beat(beat) => Some(beat),
+                _ => None,
+            })
+            .collect();
+        assert!(beats.len() >= 8, "{} beats", beats.len());
+        let bpm = beats.last().unwrap().bpm;
+        assert!((bpm - 120.0).abs() < 2.0, "{} BPM", bpm);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::Serialize;
+
+/// Samples at or above this magnitude (about -0.1 dBFS) count as near full
+/// scale. Anything at or above 1.0 counts as clipped as well.
+const NEAR_FULL_SCALE: f64 = 0.989;
+
+/// Seconds covered by the rolling part of the report.
+const ROLLING_WINDOW_SECS: usize = 10;
+
+/// Counters written by the sink for every packet it plays.
+#[derive(Default)]
+pub struct ClipCounters {
+    samples: AtomicU64,
+    clipped: AtomicU64,
+    near_full_scale: AtomicU64,
+}
+
+impl ClipCounters {
+    /// Counts a packet of samples as they go to the output device.
+    pub fn record(&self, samples: &[f64]) {
+        let (mut clipped, mut near_full_scale) = (0, 0);
+        for sample in samples {
+            let magnitude = sample.abs();
+            if magnitude >= 1.0 {
+                clipped += 1;
+            }
+            if magnitude >= NEAR_FULL_SCALE {
+                near_full_scale += 1;
+            }
+        }
+
+        self.samples
+            .fetch_add(samples.len() as u64, Ordering::Relaxed);
+        self.clipped.fetch_add(clipped, Ordering::Relaxed);
+        self.near_full_scale
+            .fetch_add(near_full_scale, Ordering::Relaxed);
+    }
+
+    /// The counts so far. The capture thread takes one with every chunk it
+    /// reads, so they are delayed along with the audio.
+    pub fn snapshot(&self) -> ClipTotals {
+        ClipTotals {
+            samples: self.samples.load(Ordering::Relaxed),
+            clipped: self.clipped.load(Ordering::Relaxed),
+            near_full_scale: self.near_full_scale.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct ClipTotals {
+    pub samples: u64,
+    pub clipped: u64,
+    pub near_full_scale: u64,
+}
+
+impl ClipTotals {
+    fn since(&self, earlier: &ClipTotals) -> ClipTotals {
+        ClipTotals {
+            samples: self.samples - earlier.samples,
+            clipped: self.clipped - earlier.clipped,
+            near_full_scale: self.near_full_scale - earlier.near_full_scale,
+        }
+    }
+
+    pub fn add(&mut self, other: &ClipTotals) {
+        self.samples += other.samples;
+        self.clipped += other.clipped;
+        self.near_full_scale += other.near_full_scale;
+    }
+}
+
+/// Payload of the `clipping_report` event.
+#[derive(Serialize, Clone)]
+pub struct ClipReport {
+    /// Counts over the last `window_secs` seconds.
+    pub rolling: ClipTotals,
+    pub window_secs: usize,
+    /// Counts since the start of the current track.
+    pub track: ClipTotals,
+}
+
+/// Turns snapshots of the sink's running counters into per-second deltas, a
+/// rolling window and per-track totals.
+pub struct ClipReporter {
+    last: ClipTotals,
+    seconds: VecDeque<ClipTotals>,
+    track: ClipTotals,
+}
+
+impl ClipReporter {
+    /// `start` is the snapshot counting starts from.
+    pub fn new(start: ClipTotals) -> Self {
+        Self {
+            last: start,
+            seconds: VecDeque::with_capacity(ROLLING_WINDOW_SECS),
+            track: ClipTotals::default(),
+        }
+    }
+
+    /// Takes the counts between the previous snapshot and `now`. Returns the
+    /// delta, to be attributed to the current track, and the updated report.
+    pub fn report(&mut self, now: ClipTotals) -> (ClipTotals, ClipReport) {
+        let delta = now.since(&self.last);
+        self.last = now;
+
+        if self.seconds.len() == ROLLING_WINDOW_SECS {
+            self.seconds.pop_front();
+        }
+        self.seconds.push_back(delta);
+        self.track.add(&delta);
+
+        let mut rolling = ClipTotals::default();
+        self.seconds.iter().for_each(|second| rolling.add(second));
+
+        (
+            delta,
+            ClipReport {
+                rolling,
+                window_secs: ROLLING_WINDOW_SECS,
+                track: self.track,
+            },
+        )
+    }
+
+    /// Starts new track totals. Call `report` first so the previous track
+    /// gets its final counts.
+    pub fn start_track(&mut self) {
+        self.track = ClipTotals::default();
+    }
+}
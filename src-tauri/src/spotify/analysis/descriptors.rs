@@ -0,0 +1,129 @@
+use serde::Serialize;
+
+use super::spectrum::{SpectralWindow, SpectrumConfig};
+
+/// Share of the spectral energy below the rolloff frequency.
+const ROLLOFF_SHARE: f32 = 0.85;
+/// Windows with less total power than this are reported as all zeros.
+const SILENCE_POWER: f32 = 1e-10;
+
+/// Payload of the `spectral_descriptors` event, one per spectrum hop.
+#[derive(Serialize, Clone, Default)]
+pub struct DescriptorFrame {
+    /// Magnitude-weighted mean frequency, the perceived "brightness".
+    pub centroid_hz: f32,
+    /// Frequency below which `ROLLOFF_SHARE` of the energy lies.
+    pub rolloff_hz: f32,
+    /// Increase of the magnitude spectrum since the previous hop, relative to
+    /// the current one, 0-1.
+    pub flux: f32,
+    /// Geometric over arithmetic mean of the power spectrum, 0 for a pure
+    /// tone up to 1 for white noise.
+    pub flatness: f32,
+    /// Share of adjacent samples that change sign, 0-1.
+    pub zero_crossing_rate: f32,
+}
+
+/// Computes the descriptors on the same hops as the spectrum, with its own
+/// FFT so either can be turned off on its own.
+pub struct DescriptorAnalyzer {
+    window: SpectralWindow,
+    extractor: DescriptorExtractor,
+}
+
+impl DescriptorAnalyzer {
+    pub fn new(config: &SpectrumConfig, sample_rate: u32, channels: usize) -> Self {
+        let window = SpectralWindow::new(config, channels);
+        Self {
+            extractor: DescriptorExtractor::new(sample_rate, window.fft_size()),
+            window,
+        }
+    }
+
+    /// Feeds interleaved samples and returns the descriptors of every
+    /// completed hop.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<DescriptorFrame> {
+        let mut frames = Vec::new();
+        self.window.process(samples, |magnitudes, mono| {
+            frames.push(self.extractor.extract(magnitudes, mono))
+        });
+        frames
+    }
+}
+
+/// Derives the descriptors of a window from its magnitude spectrum and time
+/// domain samples.
+pub struct DescriptorExtractor {
+    bin_hz: f32,
+    previous: Vec<f32>,
+}
+
+impl DescriptorExtractor {
+    pub fn new(sample_rate: u32, fft_size: usize) -> Self {
+        Self {
+            bin_hz: sample_rate as f32 / fft_size as f32,
+            previous: vec![0.0; fft_size / 2 + 1],
+        }
+    }
+
+    pub fn extract(&mut self, magnitudes: &[f32], samples: &[f32]) -> DescriptorFrame {
+        let flux = self.flux(magnitudes);
+
+        // DC says nothing about timbre
+        let bins = &magnitudes[1..];
+        let total_power = bins.iter().map(|m| m * m).sum::<f32>();
+        if total_power < SILENCE_POWER {
+            return DescriptorFrame::default();
+        }
+
+        let total_magnitude = bins.iter().sum::<f32>();
+        let centroid_hz = bins
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (i + 1) as f32 * self.bin_hz * m)
+            .sum::<f32>()
+            / total_magnitude;
+
+        let mut cumulative = 0.0;
+        let rolloff_bin = bins
+            .iter()
+            .position(|m| {
+                cumulative += m * m;
+                cumulative >= ROLLOFF_SHARE * total_power
+            })
+            .unwrap_or(bins.len() - 1);
+
+        let mean_log_power =
+            bins.iter().map(|m| (m * m).max(1e-20).ln()).sum::<f32>() / bins.len() as f32;
+        let flatness = mean_log_power.exp() / (total_power / bins.len() as f32);
+
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+            .count();
+
+        DescriptorFrame {
+            centroid_hz,
+            rolloff_hz: (rolloff_bin + 1) as f32 * self.bin_hz,
+            flux,
+            flatness: flatness.clamp(0.0, 1.0),
+            zero_crossing_rate: crossings as f32 / samples.len().saturating_sub(1).max(1) as f32,
+        }
+    }
+
+    fn flux(&mut self, magnitudes: &[f32]) -> f32 {
+        let rise = magnitudes
+            .iter()
+            .zip(&self.previous)
+            .map(|(m, p)| (m - p).max(0.0))
+            .sum::<f32>();
+        let total = magnitudes.iter().sum::<f32>();
+        self.previous.copy_from_slice(magnitudes);
+
+        if total > 0.0 {
+            (rise / total).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,126 @@
+#[derive(Debug, Clone)]
+pub struct AutoGainConfig {
+    pub enabled: bool,
+    /// RMS level (dBFS) the feed is normalized to.
+    pub target_db: f32,
+    /// Time constant for turning the gain down when the level rises.
+    pub attack_ms: f32,
+    /// Time constant for turning the gain up when the level drops.
+    pub release_ms: f32,
+    pub max_gain_db: f32,
+    pub min_gain_db: f32,
+}
+
+impl Default for AutoGainConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target_db: -14.0,
+            attack_ms: 50.0,
+            release_ms: 2000.0,
+            max_gain_db: 24.0,
+            min_gain_db: -12.0,
+        }
+    }
+}
+
+/// Time constant of the RMS level detector.
+const DETECTOR_MS: f32 = 300.0;
+/// Below this level (dBFS) the gain is held instead of chasing noise.
+const GATE_DB: f32 = -60.0;
+/// Release time of the peak detector that keeps transients below the
+/// ceiling.
+const PEAK_RELEASE_MS: f32 = 100.0;
+/// Highest sample level (dBFS) the gain may push a peak to.
+const PEAK_CEILING_DB: f32 = -1.0;
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Smoothing coefficient of a one-pole filter with the given time constant.
+fn coefficient(time_ms: f32, sample_rate: u32) -> f32 {
+    if time_ms <= 0.0 {
+        return 0.0;
+    }
+    (-1000.0 / (time_ms * sample_rate as f32)).exp()
+}
+
+/// Adaptive gain for the analysis feed. It only ever sees copies of the
+/// captured samples, the audio sent to the output device is unaffected.
+pub struct AutoGain {
+    config: AutoGainConfig,
+    channels: usize,
+    detector_coefficient: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    target: f32,
+    gate: f32,
+    min_gain: f32,
+    max_gain: f32,
+    peak_release_coefficient: f32,
+    ceiling: f32,
+
+    mean_square: f32,
+    peak: f32,
+    gain: f32,
+}
+
+impl AutoGain {
+    pub fn new(config: AutoGainConfig, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            detector_coefficient: coefficient(DETECTOR_MS, sample_rate),
+            attack_coefficient: coefficient(config.attack_ms, sample_rate),
+            release_coefficient: coefficient(config.release_ms, sample_rate),
+            target: db_to_linear(config.target_db),
+            gate: db_to_linear(GATE_DB),
+            min_gain: db_to_linear(config.min_gain_db),
+            max_gain: db_to_linear(config.max_gain_db),
+            peak_release_coefficient: coefficient(PEAK_RELEASE_MS, sample_rate),
+            ceiling: db_to_linear(PEAK_CEILING_DB),
+            mean_square: 0.0,
+            peak: 0.0,
+            gain: 1.0,
+            config,
+        }
+    }
+
+    /// Applies the gain to interleaved samples in place. The gain is capped
+    /// so the peak level stays below the ceiling, transients turn the gain
+    /// down for a moment instead of being clipped flat.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if !self.config.enabled {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let power = frame.iter().map(|s| s * s).sum::<f32>() / self.channels as f32;
+            self.mean_square = self.detector_coefficient * self.mean_square
+                + (1.0 - self.detector_coefficient) * power;
+
+            let rms = self.mean_square.sqrt();
+            if rms > self.gate {
+                let desired = (self.target / rms).clamp(self.min_gain, self.max_gain);
+                let coefficient = if desired < self.gain {
+                    self.attack_coefficient
+                } else {
+                    self.release_coefficient
+                };
+                self.gain = coefficient * self.gain + (1.0 - coefficient) * desired;
+            }
+
+            let frame_peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            self.peak = frame_peak.max(self.peak_release_coefficient * self.peak);
+            let gain = if self.peak > 0.0 {
+                self.gain.min(self.ceiling / self.peak)
+            } else {
+                self.gain
+            };
+
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+
+/// Large enough to resolve semitones from about 100 Hz at 44.1 kHz.
+const FFT_SIZE: usize = 8192;
+const HOP_SIZE: usize = 4096;
+
+const MIN_FREQ: f32 = 100.0;
+const MAX_FREQ: f32 = 5000.0;
+
+/// Seconds of audio before the first estimate, and between later ones.
+const REPORT_INTERVAL_SECS: f32 = 5.0;
+/// Frames quieter than this (mean magnitude) don't count towards the key.
+const SILENCE_THRESHOLD: f32 = 1e-5;
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Krumhansl-Kessler key profiles, starting at the tonic.
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// Payload of the `track_key` event, also returned by `get_track_key`.
+#[derive(Debug, Serialize, Clone)]
+pub struct KeyEstimate {
+    pub track_id: String,
+    /// Tonic name, e.g. "F#".
+    pub key: String,
+    /// Tonic as a pitch class, 0 = C.
+    pub pitch_class: u8,
+    pub mode: Mode,
+    /// Correlation of the track's chroma profile with the key template, 0-1.
+    pub confidence: f32,
+    /// Average chroma of the track so far, normalized to a maximum of 1.
+    pub chroma: [f32; 12],
+}
+
+/// Accumulates a chromagram over the current track and periodically
+/// estimates its key by template matching.
+pub struct KeyDetector {
+    channels: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    pending: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    /// Pitch class of each FFT bin, `None` outside the analysed range.
+    bin_pitch_class: Vec<Option<usize>>,
+
+    track_id: Option<String>,
+    chroma_sum: [f32; 12],
+    frames: usize,
+    frames_per_report: usize,
+}
+
+impl KeyDetector {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let bin_pitch_class = (0..=FFT_SIZE / 2)
+            .map(|bin| {
+                let freq = bin as f32 * bin_hz;
+                (MIN_FREQ..=MAX_FREQ).contains(&freq).then(|| {
+                    // MIDI note number, C = 0 mod 12
+                    let note = (69.0 + 12.0 * (freq / 440.0).log2()).round() as i32;
+                    note.rem_euclid(12) as usize
+                })
+            })
+            .collect();
+
+        Self {
+            channels: channels.max(1),
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            pending: Vec::with_capacity(FFT_SIZE * 2),
+            scratch: vec![Complex::new(0.0, 0.0); FFT_SIZE],
+            bin_pitch_class,
+            track_id: None,
+            chroma_sum: [0.0; 12],
+            frames: 0,
+            frames_per_report: (REPORT_INTERVAL_SECS * sample_rate as f32 / HOP_SIZE as f32)
+                as usize,
+        }
+    }
+
+    /// Starts accumulating a new track.
+    pub fn start_track(&mut self, track_id: String) {
+        self.track_id = Some(track_id);
+        self.pending.clear();
+        self.chroma_sum = [0.0; 12];
+        self.frames = 0;
+    }
+
+    /// Feeds interleaved samples and returns an updated estimate every few
+    /// seconds of non-silent audio.
+    pub fn process(&mut self, samples: &[f32]) -> Option<KeyEstimate> {
+        self.track_id.as_ref()?;
+
+        self.pending.extend(
+            samples
+                .chunks_exact(self.channels)
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32),
+        );
+
+        let mut estimate = None;
+        while self.pending.len() >= FFT_SIZE {
+            if self.accumulate_frame() && self.frames % self.frames_per_report.max(1) == 0 {
+                estimate = self.estimate();
+            }
+            self.pending.drain(..HOP_SIZE);
+        }
+        estimate
+    }
+
+    /// Adds the chroma of the current window, returns false for silence.
+    fn accumulate_frame(&mut self) -> bool {
+        for ((out, sample), w) in self.scratch.iter_mut().zip(&self.pending).zip(&self.window) {
+            *out = Complex::new(sample * w, 0.0);
+        }
+        self.fft.process(&mut self.scratch);
+
+        let mut chroma = [0.0f32; 12];
+        let mut total = 0.0;
+        for (bin, pitch_class) in self.scratch.iter().zip(&self.bin_pitch_class) {
+            if let Some(pitch_class) = pitch_class {
+                let magnitude = bin.norm() / FFT_SIZE as f32;
+                chroma[*pitch_class] += magnitude;
+                total += magnitude;
+            }
+        }
+
+        if total / (FFT_SIZE / 2) as f32 <= SILENCE_THRESHOLD {
+            return false;
+        }
+
+        // Normalize per frame so loud passages don't dominate the profile
+        for (sum, value) in self.chroma_sum.iter_mut().zip(chroma) {
+            *sum += value / total;
+        }
+        self.frames += 1;
+        true
+    }
+
+    fn estimate(&self) -> Option<KeyEstimate> {
+        let track_id = self.track_id.clone()?;
+        let max = self.chroma_sum.iter().cloned().fold(0.0, f32::max);
+        if max <= 0.0 {
+            return None;
+        }
+        let chroma = self.chroma_sum.map(|v| v / max);
+
+        let (pitch_class, mode, correlation) = (0..12)
+            .flat_map(|tonic| {
+                [
+                    (
+                        tonic,
+                        Mode::Major,
+                        correlate(&chroma, &MAJOR_PROFILE, tonic),
+                    ),
+                    (
+                        tonic,
+                        Mode::Minor,
+                        correlate(&chroma, &MINOR_PROFILE, tonic),
+                    ),
+                ]
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))?;
+
+        Some(KeyEstimate {
+            track_id,
+            key: PITCH_CLASSES[pitch_class].to_string(),
+            pitch_class: pitch_class as u8,
+            mode,
+            confidence: correlation.clamp(0.0, 1.0),
+            chroma,
+        })
+    }
+}
+
+/// Pearson correlation between the chroma vector and a key profile rotated
+/// so that its tonic lands on `tonic`.
+fn correlate(chroma: &[f32; 12], profile: &[f32; 12], tonic: usize) -> f32 {
+    let chroma_mean = chroma.iter().sum::<f32>() / 12.0;
+    let profile_mean = profile.iter().sum::<f32>() / 12.0;
+
+    let (mut covariance, mut chroma_var, mut profile_var) = (0.0, 0.0, 0.0);
+    for (pitch_class, value) in chroma.iter().enumerate() {
+        let c = value - chroma_mean;
+        let p = profile[(pitch_class + 12 - tonic) % 12] - profile_mean;
+        covariance += c * p;
+        chroma_var += c * c;
+        profile_var += p * p;
+    }
+
+    let denominator = (chroma_var * profile_var).sqrt();
+    if denominator <= f32::EPSILON {
+        0.0
+    } else {
+        covariance / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// Interleaved stereo mix of equal amplitude sines.
+    fn chord(freqs: &[f32], seconds: f32) -> Vec<f32> {
+        let frames = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let s = freqs
+                    .iter()
+                    .map(|f| (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum::<f32>()
+                    / freqs.len() as f32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn c_major_triad_is_c_major() {
+        let mut detector = KeyDetector::new(SAMPLE_RATE, 2);
+        detector.start_track("track".to_string());
+        // C4, E4, G4
+        let estimate = chord(&[261.63, 329.63, 392.0], 6.0)
+            .chunks(4096)
+            .filter_map(|c| detector.process(c))
+            .last()
+            .unwrap();
+
+        assert_eq!(estimate.key, "C");
+        assert_eq!(estimate.pitch_class, 0);
+        assert_eq!(estimate.mode, Mode::Major);
+        for (pitch_class, value) in estimate.chroma.iter().enumerate() {
+            let in_triad = [0, 4, 7].contains(&pitch_class);
+            assert_eq!(*value > 0.5, in_triad, "{:?}", estimate.chroma);
+        }
+    }
+
+    #[test]
+    fn silence_gives_no_estimate() {
+        let mut detector = KeyDetector::new(SAMPLE_RATE, 2);
+        detector.start_track("track".to_string());
+        let silence = vec![0.0; 6 * SAMPLE_RATE as usize * 2];
+        assert!(silence.chunks(4096).all(|c| detector.process(c).is_none()));
+    }
+}
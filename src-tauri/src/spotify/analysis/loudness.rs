@@ -0,0 +1,350 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+/// Loudness is accumulated in 100 ms blocks; the momentary, short-term and
+/// gating windows are all whole multiples of this.
+const BLOCK_MS: u32 = 100;
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+/// A `loudness` event is emitted every this many blocks.
+const REPORT_BLOCKS: u64 = 2;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// True-peak oversampling factor and FIR taps per polyphase branch.
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Payload of the `loudness` event. Values are `None` until there is enough
+/// signal to measure, or when the measured window is below the gate.
+#[derive(Serialize, Clone)]
+pub struct LoudnessEvent {
+    pub momentary_lufs: Option<f32>,
+    pub short_term_lufs: Option<f32>,
+    /// Gated loudness since the start of the current track.
+    pub integrated_lufs: Option<f32>,
+    /// Highest true peak since the previous event.
+    pub true_peak_dbtp: Option<f32>,
+    /// Highest true peak since the start of the current track.
+    pub max_true_peak_dbtp: Option<f32>,
+}
+
+/// Direct form I biquad.
+#[derive(Clone, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b,
+            a,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// The two stage K-weighting filter from ITU-R BS.1770, with coefficients
+/// derived for the actual sample rate.
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    // Stage 1: high shelf modelling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // Stage 2: RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    (shelf, high_pass)
+}
+
+fn to_lufs(mean_square: f64) -> Option<f32> {
+    (mean_square > 0.0).then(|| (-0.691 + 10.0 * mean_square.log10()) as f32)
+}
+
+fn to_dbtp(peak: f32) -> Option<f32> {
+    (peak > 0.0).then(|| 20.0 * peak.log10())
+}
+
+/// 4x oversampling peak detector using a windowed-sinc polyphase
+/// interpolator, one history per channel.
+struct TruePeak {
+    phases: Vec<[f32; TAPS_PER_PHASE]>,
+    history: Vec<[f32; TAPS_PER_PHASE]>,
+}
+
+impl TruePeak {
+    fn new(channels: usize) -> Self {
+        let len = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (len - 1) as f32 / 2.0;
+        let mut phases = vec![[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+        for n in 0..len {
+            let x = (n as f32 - center) / OVERSAMPLING as f32;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (std::f32::consts::PI * x).sin() / (std::f32::consts::PI * x)
+            };
+            let window =
+                0.5 - 0.5 * (2.0 * std::f32::consts::PI * (n as f32 + 0.5) / len as f32).cos();
+            phases[n % OVERSAMPLING][n / OVERSAMPLING] = sinc * window;
+        }
+
+        Self {
+            phases,
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+        }
+    }
+
+    fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        let history = &mut self.history[channel];
+        history.rotate_right(1);
+        history[0] = sample;
+
+        self.phases
+            .iter()
+            .map(|taps| {
+                taps.iter()
+                    .zip(history.iter())
+                    .map(|(t, s)| t * s)
+                    .sum::<f32>()
+                    .abs()
+            })
+            .fold(sample.abs(), f32::max)
+    }
+}
+
+/// EBU R128 momentary, short-term and integrated loudness plus true peak.
+pub struct LoudnessMeter {
+    channels: usize,
+    block_len: usize,
+    filters: Vec<(Biquad, Biquad)>,
+    true_peak: TruePeak,
+
+    /// Sum of K-weighted squares (over all channels) in the current block.
+    block_energy: f64,
+    block_fill: usize,
+    blocks: u64,
+    /// Mean square of the most recent blocks, newest last.
+    recent_blocks: VecDeque<f64>,
+    /// Mean square of every 400 ms gating block above the absolute gate
+    /// since the last reset.
+    gating_blocks: Vec<f64>,
+
+    report_peak: f32,
+    track_peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+
+        Self {
+            channels,
+            block_len: (sample_rate * BLOCK_MS / 1000) as usize,
+            filters: vec![k_weighting(sample_rate as f64); channels],
+            true_peak: TruePeak::new(channels),
+            block_energy: 0.0,
+            block_fill: 0,
+            blocks: 0,
+            recent_blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            gating_blocks: Vec::new(),
+            report_peak: 0.0,
+            track_peak: 0.0,
+        }
+    }
+
+    /// Starts a new integration period, e.g. when a new track starts. The
+    /// momentary and short-term windows keep running.
+    pub fn reset_integrated(&mut self) {
+        self.gating_blocks.clear();
+        self.track_peak = 0.0;
+    }
+
+    /// Feeds interleaved samples and returns a report every few blocks.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<LoudnessEvent> {
+        let mut reports = Vec::new();
+
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let (shelf, high_pass) = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample as f64));
+                // Left and right have a channel weight of 1.0
+                self.block_energy += weighted * weighted;
+
+                let peak = self.true_peak.process(channel, sample);
+                self.report_peak = self.report_peak.max(peak);
+                self.track_peak = self.track_peak.max(peak);
+            }
+
+            self.block_fill += 1;
+            if self.block_fill == self.block_len {
+                if let Some(report) = self.finish_block() {
+                    reports.push(report);
+                }
+            }
+        }
+
+        reports
+    }
+
+    fn finish_block(&mut self) -> Option<LoudnessEvent> {
+        if self.recent_blocks.len() == SHORT_TERM_BLOCKS {
+            self.recent_blocks.pop_front();
+        }
+        self.recent_blocks
+            .push_back(self.block_energy / self.block_len as f64);
+        self.block_energy = 0.0;
+        self.block_fill = 0;
+        self.blocks += 1;
+
+        let momentary = self.window_mean_square(MOMENTARY_BLOCKS);
+        if let Some(momentary) = momentary {
+            if to_lufs(momentary).is_some_and(|lufs| lufs as f64 > ABSOLUTE_GATE_LUFS) {
+                self.gating_blocks.push(momentary);
+            }
+        }
+
+        if self.blocks % REPORT_BLOCKS != 0 {
+            return None;
+        }
+
+        let report = LoudnessEvent {
+            momentary_lufs: momentary.and_then(to_lufs),
+            short_term_lufs: self.window_mean_square(SHORT_TERM_BLOCKS).and_then(to_lufs),
+            integrated_lufs: self.integrated(),
+            true_peak_dbtp: to_dbtp(self.report_peak),
+            max_true_peak_dbtp: to_dbtp(self.track_peak),
+        };
+        self.report_peak = 0.0;
+        Some(report)
+    }
+
+    /// Mean square over the newest `blocks` blocks, once that many exist.
+    fn window_mean_square(&self, blocks: usize) -> Option<f64> {
+        (self.recent_blocks.len() >= blocks)
+            .then(|| self.recent_blocks.iter().rev().take(blocks).sum::<f64>() / blocks as f64)
+    }
+
+    fn integrated(&self) -> Option<f32> {
+        if self.gating_blocks.is_empty() {
+            return None;
+        }
+
+        let ungated = self.gating_blocks.iter().sum::<f64>() / self.gating_blocks.len() as f64;
+        let relative_gate = to_lufs(ungated)? as f64 + RELATIVE_GATE_LU;
+
+        let (sum, count) = self
+            .gating_blocks
+            .iter()
+            .filter(|&&block| to_lufs(block).is_some_and(|lufs| lufs as f64 > relative_gate))
+            .fold((0.0, 0usize), |(sum, count), block| {
+                (sum + block, count + 1)
+            });
+
+        (count > 0).then(|| sum / count as f64).and_then(to_lufs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Interleaved stereo sine, `left` and `right` are the channel
+    /// amplitudes.
+    fn sine(freq: f32, left: f32, right: f32, seconds: f32) -> Vec<f32> {
+        let frames = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s = (2.0 * std::f32::consts::PI * freq * i as f32 / SAMPLE_RATE as f32).sin();
+                [s * left, s * right]
+            })
+            .collect()
+    }
+
+    fn integrated(chunks: &[&[f32]]) -> f32 {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE, 2);
+        let reports: Vec<_> = chunks.iter().flat_map(|c| meter.process(c)).collect();
+        reports.last().unwrap().integrated_lufs.unwrap()
+    }
+
+    #[test]
+    fn full_scale_sine_in_one_channel_is_minus_3_lufs() {
+        // The BS.1770 calibration signal, 997 Hz at 0 dBFS
+        let lufs = integrated(&[&sine(997.0, 1.0, 0.0, 3.0)]);
+        assert!((lufs + 3.01).abs() < 0.05, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn full_scale_sine_in_both_channels_is_0_lufs() {
+        let lufs = integrated(&[&sine(1000.0, 1.0, 1.0, 3.0)]);
+        assert!(lufs.abs() < 0.05, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn absolute_gate_excludes_silence() {
+        let tone = sine(1000.0, 0.1, 0.1, 3.0);
+        let silence = vec![0.0; tone.len()];
+        let alone = integrated(&[&tone]);
+        let with_silence = integrated(&[&tone, &silence, &tone, &silence]);
+        // Ungated it would be 3 LU lower, only the blocks spanning an edge
+        // are partly silent
+        assert!(
+            (alone - with_silence).abs() < 0.5,
+            "{} vs {}",
+            alone,
+            with_silence
+        );
+    }
+
+    #[test]
+    fn relative_gate_excludes_quiet_passages() {
+        let loud = sine(1000.0, 0.5, 0.5, 3.0);
+        // 30 dB down, above the absolute gate but below the relative one
+        let quiet = sine(1000.0, 0.5 * 0.0316, 0.5 * 0.0316, 3.0);
+        let alone = integrated(&[&loud]);
+        let with_quiet = integrated(&[&loud, &quiet]);
+        assert!(
+            (alone - with_quiet).abs() < 0.3,
+            "{} vs {}",
+            alone,
+            with_quiet
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use librespot::playback::{NUM_CHANNELS, SAMPLE_RATE};
+use log::{error, info};
+use tauri::State;
+
+mod analyzers;
+pub mod beat;
+pub mod clipping;
+pub mod descriptors;
+pub mod gain;
+pub mod key;
+pub mod loudness;
+pub mod overview;
+pub mod pipeline;
+pub mod pitch;
+pub mod position;
+pub mod silence;
+pub mod spectrum;
+pub mod stereo;
+pub mod structure;
+
+use analyzers::ClippingAnalyzer;
+use beat::BeatTracker;
+use clipping::{ClipCounters, ClipTotals};
+use descriptors::DescriptorAnalyzer;
+use gain::{AutoGain, AutoGainConfig};
+use key::{KeyDetector, KeyEstimate};
+use loudness::LoudnessMeter;
+use overview::{load_overview, OverviewBuilder, TrackOverview};
+use pipeline::{Analyzer, AnalyzerInput, AnalyzerWorker, EventSink, PipelineConfig, SampleBlock};
+use pitch::{PitchConfig, PitchTracker};
+use position::PlaybackPosition;
+use silence::{SilenceConfig, SilenceDetector};
+use spectrum::{SpectrumAnalyzer, SpectrumConfig};
+use stereo::{StereoAnalyzer, StereoConfig};
+use structure::{load_structure, StructureAnalyzer, TrackStructure};
+
+/// Subdirectory of the app data dir holding the per-track overviews.
+const OVERVIEW_DIR: &str = "overviews";
+/// Subdirectory of the app data dir holding the per-track segmentations.
+const STRUCTURE_DIR: &str = "structures";
+
+/// Player state changes the analyses care about, forwarded from the player
+/// event listener.
+#[derive(Clone)]
+pub enum AnalysisControl {
+    TrackChanged { track_id: String, duration_ms: u32 },
+    Playing { position_ms: u32 },
+    Seeked { position_ms: u32 },
+    Paused,
+    Stopped,
+}
+
+/// Per-track analysis results, managed by Tauri so commands can read them.
+#[derive(Default)]
+pub struct AnalysisStore {
+    pub keys: Mutex<HashMap<String, KeyEstimate>>,
+    pub clips: Mutex<HashMap<String, ClipTotals>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisConfig {
+    /// Where per-track results are stored, normally the app data dir.
+    pub data_dir: PathBuf,
+    pub spectrum: SpectrumConfig,
+    pub stereo: StereoConfig,
+    pub auto_gain: AutoGainConfig,
+    pub silence: SilenceConfig,
+    pub pitch: PitchConfig,
+    pub pipeline: PipelineConfig,
+}
+
+/// Runs the adaptive gain stage on the captured samples and hands every
+/// chunk, with its timing, to the registered analyzers.
+pub struct Analysis {
+    config: PipelineConfig,
+    sink: EventSink,
+    position: PlaybackPosition,
+    frames: u64,
+    auto_gain: AutoGain,
+    workers: Vec<AnalyzerWorker>,
+}
+
+impl Analysis {
+    pub fn new(config: AnalysisConfig, clip_counters: &ClipCounters, sink: EventSink) -> Self {
+        let channels = NUM_CHANNELS as usize;
+        let mut analysis = Self {
+            config: config.pipeline,
+            sink,
+            position: PlaybackPosition::new(SAMPLE_RATE),
+            frames: 0,
+            auto_gain: AutoGain::new(config.auto_gain, SAMPLE_RATE, channels),
+            workers: Vec::new(),
+        };
+
+        analysis.register(Box::new(ClippingAnalyzer::new(clip_counters.snapshot())));
+        analysis.register(Box::new(LoudnessMeter::new(SAMPLE_RATE, channels)));
+        analysis.register(Box::new(SilenceDetector::new(
+            config.silence,
+            SAMPLE_RATE,
+            channels,
+        )));
+        analysis.register(Box::new(OverviewBuilder::new(
+            config.data_dir.join(OVERVIEW_DIR),
+            SAMPLE_RATE,
+            channels,
+        )));
+        analysis.register(Box::new(StructureAnalyzer::new(
+            config.data_dir.join(STRUCTURE_DIR),
+            SAMPLE_RATE,
+            channels,
+        )));
+        analysis.register(Box::new(DescriptorAnalyzer::new(
+            &config.spectrum,
+            SAMPLE_RATE,
+            channels,
+        )));
+        analysis.register(Box::new(SpectrumAnalyzer::new(
+            config.spectrum,
+            SAMPLE_RATE,
+            channels,
+        )));
+        analysis.register(Box::new(BeatTracker::new(SAMPLE_RATE, channels)));
+        analysis.register(Box::new(KeyDetector::new(SAMPLE_RATE, channels)));
+        analysis.register(Box::new(StereoAnalyzer::new(
+            config.stereo,
+            SAMPLE_RATE,
+            channels,
+        )));
+        if config.pitch.enabled {
+            analysis.register(Box::new(PitchTracker::new(SAMPLE_RATE, channels)));
+        }
+
+        analysis
+    }
+
+    /// Starts a worker for the analyzer unless it is turned off in the
+    /// config.
+    pub fn register(&mut self, analyzer: Box<dyn Analyzer>) {
+        let name = analyzer.name();
+        if self.config.disabled.iter().any(|disabled| disabled == name) {
+            info!("Analyzer {} is disabled", name);
+            return;
+        }
+
+        match AnalyzerWorker::spawn(analyzer, self.config.cpu_budget, self.sink.clone()) {
+            Ok(worker) => self.workers.push(worker),
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    /// Track position of the next captured frame.
+    pub fn position_ms(&self) -> f64 {
+        self.position.ms()
+    }
+
+    pub fn handle_control(&mut self, control: AnalysisControl) {
+        match control {
+            AnalysisControl::TrackChanged { .. } => self.position.set_ms(0),
+            AnalysisControl::Playing { position_ms } | AnalysisControl::Seeked { position_ms } => {
+                self.position.set_ms(position_ms)
+            }
+            AnalysisControl::Paused | AnalysisControl::Stopped => {}
+        }
+
+        for worker in &self.workers {
+            worker.send_control(control.clone());
+        }
+    }
+
+    /// Dispatches a captured chunk, with the clip counters read along with
+    /// it. Metering analyzers get the samples as captured, after which the
+    /// chunk is normalized in place for the visual analyzers and the
+    /// `audio_chunk` feed.
+    pub fn process(&mut self, samples: &mut [f32], clips: ClipTotals) {
+        self.dispatch(AnalyzerInput::Captured, samples, clips);
+        self.auto_gain.process(samples);
+        self.dispatch(AnalyzerInput::Normalized, samples, clips);
+
+        let frames = samples.len() / NUM_CHANNELS as usize;
+        self.position.advance(frames);
+        self.frames += frames as u64;
+    }
+
+    fn dispatch(&mut self, input: AnalyzerInput, samples: &[f32], clips: ClipTotals) {
+        if !self.workers.iter().any(|worker| worker.input() == input) {
+            return;
+        }
+
+        let block = Arc::new(SampleBlock {
+            samples: samples.to_vec(),
+            sample_rate: SAMPLE_RATE,
+            channels: NUM_CHANNELS as usize,
+            start_frame: self.frames,
+            position_ms: self.position.ms(),
+            clips,
+        });
+        for worker in self.workers.iter_mut().filter(|w| w.input() == input) {
+            worker.send_block(block.clone());
+        }
+    }
+}
+
+/// Waits for the workers, so analyzers that save their results are done
+/// when the pipeline that owned them is.
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        // Closed first so the workers finish in parallel
+        let threads: Vec<_> = self.workers.drain(..).map(AnalyzerWorker::close).collect();
+        for thread in threads {
+            if thread.join().is_err() {
+                error!("Analyzer thread panicked");
+            }
+        }
+    }
+}
+
+/// Whether `track_id` is a base62 Spotify ID, as the player events carry
+/// them. Commands check IDs from the webview with it before they are used
+/// in file names.
+fn is_track_id(track_id: &str) -> bool {
+    track_id.len() == 22 && track_id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[tauri::command]
+pub fn get_track_key(state: State<'_, AnalysisStore>, track_id: String) -> Option<KeyEstimate> {
+    state.keys.lock().unwrap().get(&track_id).cloned()
+}
+
+/// The stored waveform and spectrogram of a track, if it was played before.
+#[tauri::command]
+pub fn get_track_overview(
+    state: State<'_, crate::AppConfigState>,
+    track_id: String,
+) -> Option<TrackOverview> {
+    if !is_track_id(&track_id) {
+        return None;
+    }
+    load_overview(&state.app_dir.join(OVERVIEW_DIR), &track_id)
+}
+
+/// The stored section boundaries of a track, if enough of it was played
+/// before.
+#[tauri::command]
+pub fn get_track_structure(
+    state: State<'_, crate::AppConfigState>,
+    track_id: String,
+) -> Option<TrackStructure> {
+    if !is_track_id(&track_id) {
+        return None;
+    }
+    load_structure(&state.app_dir.join(STRUCTURE_DIR), &track_id)
+}
+
+/// Clipped and near-full-scale sample counts for every track played so far.
+#[tauri::command]
+pub fn get_clip_totals(state: State<'_, AnalysisStore>) -> HashMap<String, ClipTotals> {
+    state.clips.lock().unwrap().clone()
+}
@@ -0,0 +1,339 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+const WAVEFORM_BIN_MS: u32 = 50;
+const SPECTROGRAM_FRAME_MS: u32 = 200;
+const MEL_BANDS: usize = 24;
+const MEL_MIN_FREQ: f32 = 40.0;
+const MEL_MAX_FREQ: f32 = 16000.0;
+const FFT_SIZE: usize = 2048;
+const DB_FLOOR: f32 = -80.0;
+
+/// Overviews are only (re)written when at least this share of the track has
+/// been seen, so a skipped track doesn't replace a complete one.
+const MIN_SAVE_COVERAGE: f32 = 0.5;
+/// Seconds of audio between saves while a track plays, so a crash loses at
+/// most this much of it.
+const SAVE_INTERVAL_SECS: usize = 30;
+
+/// A compact picture of a whole track, stored as JSON in the app data dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackOverview {
+    pub track_id: String,
+    pub duration_ms: u32,
+    pub waveform_bin_ms: u32,
+    /// `[peak, rms]` per bin, scaled to 0-255 of full scale.
+    pub waveform: Vec<[u8; 2]>,
+    pub spectrogram_frame_ms: u32,
+    pub mel_bands: usize,
+    /// One row of `mel_bands` levels per frame, 0-255 covering -80..0 dBFS.
+    pub spectrogram: Vec<Vec<u8>>,
+    /// Share of waveform bins that were filled from actual audio.
+    pub coverage: f32,
+}
+
+impl TrackOverview {
+    fn empty(track_id: String, duration_ms: u32) -> Self {
+        let bins = duration_ms.div_ceil(WAVEFORM_BIN_MS) as usize;
+        let frames = duration_ms.div_ceil(SPECTROGRAM_FRAME_MS) as usize;
+
+        Self {
+            track_id,
+            duration_ms,
+            waveform_bin_ms: WAVEFORM_BIN_MS,
+            waveform: vec![[0, 0]; bins],
+            spectrogram_frame_ms: SPECTROGRAM_FRAME_MS,
+            mel_bands: MEL_BANDS,
+            spectrogram: vec![vec![0; MEL_BANDS]; frames],
+            coverage: 0.0,
+        }
+    }
+
+    /// Whether a stored overview can be merged into, i.e. was built with the
+    /// same layout.
+    fn is_compatible(&self, duration_ms: u32) -> bool {
+        self.duration_ms == duration_ms
+            && self.waveform_bin_ms == WAVEFORM_BIN_MS
+            && self.spectrogram_frame_ms == SPECTROGRAM_FRAME_MS
+            && self.mel_bands == MEL_BANDS
+    }
+}
+
+fn overview_path(dir: &Path, track_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", track_id))
+}
+
+pub fn load_overview(dir: &Path, track_id: &str) -> Option<TrackOverview> {
+    let content = fs::read_to_string(overview_path(dir, track_id)).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(overview) => Some(overview),
+        Err(e) => {
+            warn!("Ignoring unreadable overview for {}: {}", track_id, e);
+            None
+        }
+    }
+}
+
+fn save_overview(dir: &Path, overview: &TrackOverview) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let content = serde_json::to_string(overview)?;
+    fs::write(overview_path(dir, &overview.track_id), content)
+}
+
+/// Accumulates the waveform and mel spectrogram of the current track.
+pub struct OverviewBuilder {
+    dir: PathBuf,
+    channels: usize,
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Triangular filters as `(first_bin, weights)`.
+    mel_filters: Vec<(usize, Vec<f32>)>,
+
+    overview: Option<TrackOverview>,
+    filled: Vec<bool>,
+    changed: bool,
+    frames_since_save: usize,
+
+    /// Waveform bin being accumulated: index, peak, sum of squares, count.
+    bin: Option<(usize, f32, f32, usize)>,
+    /// Spectrogram frame being collected and its mono samples.
+    frame: Option<usize>,
+    frame_samples: Vec<f32>,
+}
+
+impl OverviewBuilder {
+    pub fn new(dir: PathBuf, sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        Self {
+            dir,
+            channels: channels.max(1),
+            sample_rate,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            mel_filters: mel_filters(sample_rate as f32),
+            overview: None,
+            filled: Vec::new(),
+            changed: false,
+            frames_since_save: 0,
+            bin: None,
+            frame: None,
+            frame_samples: Vec::with_capacity(FFT_SIZE),
+        }
+    }
+
+    /// Saves the previous track and starts a new one. Returns the stored
+    /// overview of the new track, if it was played before.
+    pub fn start_track(&mut self, track_id: String, duration_ms: u32) -> Option<TrackOverview> {
+        self.save();
+        self.bin = None;
+        self.frame = None;
+        self.frames_since_save = 0;
+
+        let stored = load_overview(&self.dir, &track_id);
+        let overview = match &stored {
+            Some(stored) if stored.is_compatible(duration_ms) => stored.clone(),
+            _ => TrackOverview::empty(track_id, duration_ms),
+        };
+        self.filled = vec![false; overview.waveform.len()];
+        self.overview = Some(overview);
+        stored
+    }
+
+    /// Writes the current track to disk if enough of it was heard since it
+    /// started or was last saved.
+    pub fn save(&mut self) {
+        self.flush_bin();
+        self.write();
+    }
+
+    /// Like `save`, but leaves the waveform bin being accumulated alone.
+    fn write(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+
+        let Some(overview) = self.overview.as_mut() else {
+            return;
+        };
+        let filled = self.filled.iter().filter(|&&f| f).count();
+        let coverage = filled as f32 / self.filled.len().max(1) as f32;
+        if coverage < MIN_SAVE_COVERAGE && coverage <= overview.coverage {
+            return;
+        }
+
+        overview.coverage = coverage.max(overview.coverage);
+        match save_overview(&self.dir, overview) {
+            Ok(()) => info!(
+                "Stored overview for {} ({:.0}% covered)",
+                overview.track_id,
+                overview.coverage * 100.0
+            ),
+            Err(e) => warn!("Failed to store overview for {}: {}", overview.track_id, e),
+        }
+    }
+
+    /// Playback jumped, don't mix samples from before and after.
+    pub fn seeked(&mut self) {
+        self.flush_bin();
+        self.bin = None;
+        self.frame = None;
+    }
+
+    /// Feeds interleaved samples starting at `position_ms` in the track.
+    pub fn process(&mut self, samples: &[f32], position_ms: f64) {
+        if self.overview.is_none() {
+            return;
+        }
+
+        let frame_ms = 1000.0 / self.sample_rate as f64;
+        for (i, frame) in samples.chunks_exact(self.channels).enumerate() {
+            let ms = position_ms + i as f64 * frame_ms;
+            let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let mono = frame.iter().sum::<f32>() / self.channels as f32;
+            self.add_to_waveform(ms, peak, mono);
+            self.add_to_spectrogram(ms, mono);
+        }
+
+        self.frames_since_save += samples.len() / self.channels;
+        if self.frames_since_save >= SAVE_INTERVAL_SECS * self.sample_rate as usize {
+            self.frames_since_save = 0;
+            self.write();
+        }
+    }
+
+    fn add_to_waveform(&mut self, ms: f64, peak: f32, mono: f32) {
+        let index = (ms / WAVEFORM_BIN_MS as f64) as usize;
+        if self.bin.is_some_and(|(bin, ..)| bin != index) {
+            self.flush_bin();
+        }
+        let (_, bin_peak, sum_squares, count) = self.bin.get_or_insert((index, 0.0, 0.0, 0));
+        *bin_peak = bin_peak.max(peak);
+        *sum_squares += mono * mono;
+        *count += 1;
+    }
+
+    fn flush_bin(&mut self) {
+        let (Some((index, peak, sum_squares, count)), Some(overview)) =
+            (self.bin.take(), self.overview.as_mut())
+        else {
+            return;
+        };
+        if index >= overview.waveform.len() || count == 0 {
+            return;
+        }
+
+        let rms = (sum_squares / count as f32).sqrt();
+        overview.waveform[index] = [to_byte(peak), to_byte(rms)];
+        self.filled[index] = true;
+        self.changed = true;
+    }
+
+    fn add_to_spectrogram(&mut self, ms: f64, mono: f32) {
+        let index = (ms / SPECTROGRAM_FRAME_MS as f64) as usize;
+        if self.frame != Some(index) {
+            self.frame = Some(index);
+            self.frame_samples.clear();
+        }
+        // Only the first FFT_SIZE samples of each frame are analysed
+        if self.frame_samples.len() < FFT_SIZE {
+            self.frame_samples.push(mono);
+            if self.frame_samples.len() == FFT_SIZE {
+                self.analyze_frame(index);
+            }
+        }
+    }
+
+    fn analyze_frame(&mut self, index: usize) {
+        let Some(overview) = self.overview.as_mut() else {
+            return;
+        };
+        if index >= overview.spectrogram.len() {
+            return;
+        }
+
+        let mut buffer: Vec<Complex<f32>> = self
+            .frame_samples
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+
+        let scale = 4.0 / FFT_SIZE as f32;
+        let row = &mut overview.spectrogram[index];
+        for (level, (first_bin, weights)) in row.iter_mut().zip(&self.mel_filters) {
+            let energy = weights
+                .iter()
+                .zip(&buffer[*first_bin..])
+                .map(|(w, bin)| w * (bin.norm() * scale).powi(2))
+                .sum::<f32>()
+                / weights.iter().sum::<f32>().max(f32::EPSILON);
+            let db = 10.0 * energy.max(1e-12).log10();
+            *level = (((db - DB_FLOOR) / -DB_FLOOR).clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        self.changed = true;
+    }
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+fn mel_filters(sample_rate: f32) -> Vec<(usize, Vec<f32>)> {
+    let bin_hz = sample_rate / FFT_SIZE as f32;
+    let max_freq = MEL_MAX_FREQ.min(sample_rate / 2.0);
+    let (min_mel, max_mel) = (hz_to_mel(MEL_MIN_FREQ), hz_to_mel(max_freq));
+    let points: Vec<f32> = (0..MEL_BANDS + 2)
+        .map(|i| mel_to_hz(min_mel + (max_mel - min_mel) * i as f32 / (MEL_BANDS + 1) as f32))
+        .collect();
+
+    points
+        .windows(3)
+        .map(|edges| {
+            let (lo, center, hi) = (edges[0], edges[1], edges[2]);
+            let first_bin = (lo / bin_hz).floor() as usize;
+            let last_bin = ((hi / bin_hz).ceil() as usize).max(first_bin + 1);
+            let weights = (first_bin..=last_bin)
+                .map(|bin| {
+                    let freq = bin as f32 * bin_hz;
+                    if freq <= center {
+                        (freq - lo) / (center - lo)
+                    } else {
+                        (hi - freq) / (hi - center)
+                    }
+                    .max(0.0)
+                })
+                .collect::<Vec<f32>>();
+            // Very narrow low bands can miss every bin; use the nearest one
+            if weights.iter().all(|&w| w == 0.0) {
+                let nearest = (center / bin_hz).round() as usize;
+                (nearest, vec![1.0])
+            } else {
+                (first_bin, weights)
+            }
+        })
+        .collect()
+}
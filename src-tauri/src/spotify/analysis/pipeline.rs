@@ -0,0 +1,296 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{unbounded, Sender};
+use log::{error, info, warn};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use super::{clipping::ClipTotals, AnalysisControl};
+
+/// Blocks queued per worker before skippable analyzers drop new ones.
+const WORKER_QUEUE: usize = 32;
+/// CPU use is averaged over this much audio before the budget resets.
+const BUDGET_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    /// Names of analyzers that are not started.
+    pub disabled: Vec<String>,
+    /// Share of a core each analyzer may use relative to real time.
+    pub cpu_budget: f32,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            cpu_budget: 0.25,
+        }
+    }
+}
+
+/// A chunk of captured audio with where it belongs in time.
+pub struct SampleBlock {
+    /// Interleaved samples.
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Frames captured before this block since the pipeline started.
+    pub start_frame: u64,
+    /// Track position of the block's first frame.
+    pub position_ms: f64,
+    /// The sink's clip counters as of the block's last frame.
+    pub clips: ClipTotals,
+}
+
+impl SampleBlock {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Which version of the captured audio an analyzer wants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyzerInput {
+    /// As played, for metering.
+    Captured,
+    /// After the adaptive gain stage, for visuals.
+    Normalized,
+}
+
+/// Where analyzers publish their results.
+#[derive(Clone)]
+pub struct EventSink {
+    app_handle: AppHandle,
+}
+
+impl EventSink {
+    pub fn new(app_handle: AppHandle) -> Self {
+        Self { app_handle }
+    }
+
+    pub fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        if let Err(e) = self.app_handle.emit(event, payload) {
+            error!("Failed to emit {}: {}", event, e);
+        }
+    }
+
+    /// For analyzers that also keep results in managed state.
+    pub fn app_handle(&self) -> &AppHandle {
+        &self.app_handle
+    }
+}
+
+/// An analysis run on the captured audio. Each registered analyzer gets its
+/// own worker thread, so a slow one can't hold up the others or the feed.
+pub trait Analyzer: Send + 'static {
+    /// Used in logs and to turn the analyzer off in the config.
+    fn name(&self) -> &'static str;
+
+    fn input(&self) -> AnalyzerInput {
+        AnalyzerInput::Normalized
+    }
+
+    /// Analyzers that add up results over the whole track, such as the
+    /// integrated loudness or the overview, must see every block. Their
+    /// blocks queue up instead of being dropped while the worker is behind,
+    /// and the CPU budget only warns when they go over it.
+    fn skippable(&self) -> bool {
+        true
+    }
+
+    fn analyze(&mut self, block: &SampleBlock, sink: &EventSink);
+
+    fn control(&mut self, _control: &AnalysisControl, _sink: &EventSink) {}
+
+    /// Called once the pipeline stops, after the last queued message.
+    fn finish(&mut self, _sink: &EventSink) {}
+}
+
+enum WorkerMessage {
+    Block(Arc<SampleBlock>),
+    Control(AnalysisControl),
+}
+
+/// Limits the time an analyzer spends per second of audio. Blocks arriving
+/// after the budget ran out are skipped until the next window, unless the
+/// analyzer isn't skippable.
+struct CpuBudget {
+    share: f32,
+    skippable: bool,
+    audio: Duration,
+    busy: Duration,
+    /// Blocks that arrived over budget in this window.
+    over: usize,
+}
+
+impl CpuBudget {
+    fn new(share: f32, skippable: bool) -> Self {
+        Self {
+            share,
+            skippable,
+            audio: Duration::ZERO,
+            busy: Duration::ZERO,
+            over: 0,
+        }
+    }
+
+    /// Accounts for a block and tells whether it may be analysed.
+    fn admit(&mut self, block: &SampleBlock, name: &str) -> bool {
+        if self.audio >= BUDGET_WINDOW {
+            if self.over > 0 && self.skippable {
+                warn!(
+                    "Analyzer {} over its CPU budget, skipped {} blocks",
+                    name, self.over
+                );
+            } else if self.over > 0 {
+                warn!(
+                    "Analyzer {} over its CPU budget for {} blocks",
+                    name, self.over
+                );
+            }
+            *self = Self::new(self.share, self.skippable);
+        }
+        self.audio += block.duration();
+
+        if self.busy.as_secs_f32() > self.share * self.audio.as_secs_f32() {
+            self.over += 1;
+            return !self.skippable;
+        }
+        true
+    }
+
+    fn spend(&mut self, elapsed: Duration) {
+        self.busy += elapsed;
+    }
+}
+
+/// The dispatching end of an analyzer's worker thread. The thread finishes
+/// the analyzer and ends when this is dropped, `close` waits for that.
+pub struct AnalyzerWorker {
+    name: &'static str,
+    input: AnalyzerInput,
+    /// Unbounded so control messages never wait, `queued` bounds the blocks
+    /// of skippable analyzers.
+    sender: Sender<WorkerMessage>,
+    queued: Arc<AtomicUsize>,
+    skippable: bool,
+    lagging: bool,
+    /// Blocks dropped since the worker fell behind.
+    dropped: usize,
+    thread: JoinHandle<()>,
+}
+
+impl AnalyzerWorker {
+    /// Starts a worker that may use `cpu_budget` of a core relative to real
+    /// time, e.g. 0.25 for 250 ms per second of audio.
+    pub fn spawn(
+        mut analyzer: Box<dyn Analyzer>,
+        cpu_budget: f32,
+        sink: EventSink,
+    ) -> Result<Self, String> {
+        let name = analyzer.name();
+        let input = analyzer.input();
+        let skippable = analyzer.skippable();
+        let (sender, receiver) = unbounded::<WorkerMessage>();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let worker_queued = queued.clone();
+
+        let thread = thread::Builder::new()
+            .name(format!("analyzer-{}", name))
+            .spawn(move || {
+                let mut budget = CpuBudget::new(cpu_budget, skippable);
+                for message in receiver {
+                    match message {
+                        WorkerMessage::Block(block) => {
+                            worker_queued.fetch_sub(1, Ordering::Relaxed);
+                            if !budget.admit(&block, name) {
+                                continue;
+                            }
+                            let started = Instant::now();
+                            analyzer.analyze(&block, &sink);
+                            budget.spend(started.elapsed());
+                        }
+                        WorkerMessage::Control(control) => analyzer.control(&control, &sink),
+                    }
+                }
+                analyzer.finish(&sink);
+                info!("Analyzer {} stopped", name);
+            })
+            .map_err(|e| format!("Failed to start analyzer {}: {}", name, e))?;
+
+        Ok(Self {
+            name,
+            input,
+            sender,
+            queued,
+            skippable,
+            lagging: false,
+            dropped: 0,
+            thread,
+        })
+    }
+
+    pub fn input(&self) -> AnalyzerInput {
+        self.input
+    }
+
+    /// Queues a block. If the worker is too far behind, skippable analyzers
+    /// drop it and the others queue it anyway.
+    pub fn send_block(&mut self, block: Arc<SampleBlock>) {
+        let behind = self.queued.load(Ordering::Relaxed) >= WORKER_QUEUE;
+        if behind && !self.lagging {
+            self.lagging = true;
+            if self.skippable {
+                warn!("Analyzer {} is falling behind, dropping blocks", self.name);
+            } else {
+                warn!("Analyzer {} is falling behind, queueing blocks", self.name);
+            }
+        }
+        if behind && self.skippable {
+            self.dropped += 1;
+            return;
+        }
+
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(WorkerMessage::Block(block)).is_err() {
+            return;
+        }
+        if self.lagging && !behind {
+            self.lagging = false;
+            if self.dropped > 0 {
+                warn!(
+                    "Analyzer {} caught up after dropping {} blocks",
+                    self.name, self.dropped
+                );
+                self.dropped = 0;
+            } else {
+                info!("Analyzer {} caught up", self.name);
+            }
+        }
+    }
+
+    /// Control messages are never dropped and never wait, they are queued
+    /// in order with the blocks.
+    pub fn send_control(&self, control: AnalysisControl) {
+        let _ = self.sender.send(WorkerMessage::Control(control));
+    }
+
+    /// Lets the worker run through its queue and finish the analyzer.
+    /// Returns the thread to wait for.
+    pub fn close(self) -> JoinHandle<()> {
+        drop(self.sender);
+        self.thread
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Serialize;
+
+/// Analysis window and hop, in frames.
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = 1024;
+
+/// Vocal melody range searched by the tracker.
+const MIN_FREQ: f32 = 80.0;
+const MAX_FREQ: f32 = 1000.0;
+
+/// YIN absolute threshold on the cumulative mean normalized difference.
+const YIN_THRESHOLD: f32 = 0.15;
+/// Frames below this RMS are reported as unvoiced without analysis.
+const SILENCE_RMS: f32 = 1e-3;
+
+#[derive(Debug, Clone, Default)]
+pub struct PitchConfig {
+    /// Off by default, the YIN difference function is the most expensive
+    /// analysis per block. Enabled with the `pitch_enabled` setting.
+    pub enabled: bool,
+}
+
+/// Payload of the `pitch` event.
+#[derive(Serialize, Clone)]
+pub struct PitchFrame {
+    /// Track position of the analysed window's centre, comparable with the
+    /// synced lyrics timestamps.
+    pub position_ms: f64,
+    /// `None` when no periodic melody was found.
+    pub frequency_hz: Option<f32>,
+    /// Fractional MIDI note number of `frequency_hz`.
+    pub midi_note: Option<f32>,
+    /// 0-1, how periodic the window is at the detected pitch.
+    pub confidence: f32,
+}
+
+/// One-pole filter pair band-limiting the mid signal to the melody range
+/// before YIN, which keeps bass and cymbals from dominating.
+struct BandLimit {
+    high_pass_coefficient: f32,
+    low_pass_coefficient: f32,
+    previous_input: f32,
+    high_passed: f32,
+    low_passed: f32,
+}
+
+impl BandLimit {
+    fn new(sample_rate: f32) -> Self {
+        let rc_high = 1.0 / (2.0 * std::f32::consts::PI * MIN_FREQ);
+        let rc_low = 1.0 / (2.0 * std::f32::consts::PI * MAX_FREQ * 1.5);
+        let dt = 1.0 / sample_rate;
+
+        Self {
+            high_pass_coefficient: rc_high / (rc_high + dt),
+            low_pass_coefficient: dt / (rc_low + dt),
+            previous_input: 0.0,
+            high_passed: 0.0,
+            low_passed: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.high_passed =
+            self.high_pass_coefficient * (self.high_passed + input - self.previous_input);
+        self.previous_input = input;
+        self.low_passed += self.low_pass_coefficient * (self.high_passed - self.low_passed);
+        self.low_passed
+    }
+}
+
+/// YIN pitch tracker over the mid (L+R) channel.
+pub struct PitchTracker {
+    channels: usize,
+    sample_rate: f32,
+    min_lag: usize,
+    max_lag: usize,
+    filter: BandLimit,
+    pending: Vec<f32>,
+    difference: Vec<f32>,
+}
+
+impl PitchTracker {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+        let max_lag = ((sample_rate / MIN_FREQ).ceil() as usize).min(FRAME_SIZE / 2);
+
+        Self {
+            channels: channels.max(1),
+            sample_rate,
+            min_lag: (sample_rate / MAX_FREQ).floor() as usize,
+            max_lag,
+            filter: BandLimit::new(sample_rate),
+            pending: Vec::with_capacity(FRAME_SIZE * 2),
+            difference: vec![0.0; max_lag + 1],
+        }
+    }
+
+    /// Playback jumped, drop the partial window.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Feeds interleaved samples whose first frame plays at `position_ms`.
+    pub fn process(&mut self, samples: &[f32], position_ms: f64) -> Vec<PitchFrame> {
+        for frame in samples.chunks_exact(self.channels) {
+            let mid = frame.iter().sum::<f32>() / self.channels as f32;
+            self.pending.push(self.filter.process(mid));
+        }
+
+        let ms_per_frame = 1000.0 / self.sample_rate as f64;
+        let end_ms = position_ms + (samples.len() / self.channels) as f64 * ms_per_frame;
+
+        let mut frames = Vec::new();
+        while self.pending.len() >= FRAME_SIZE {
+            let centre_ms = end_ms - (self.pending.len() - FRAME_SIZE / 2) as f64 * ms_per_frame;
+            let (frequency_hz, confidence) = self.analyze_window();
+
+            frames.push(PitchFrame {
+                position_ms: centre_ms.max(0.0),
+                frequency_hz,
+                midi_note: frequency_hz.map(|f| 69.0 + 12.0 * (f / 440.0).log2()),
+                confidence,
+            });
+            self.pending.drain(..HOP_SIZE);
+        }
+        frames
+    }
+
+    fn analyze_window(&mut self) -> (Option<f32>, f32) {
+        let window = &self.pending[..FRAME_SIZE];
+        let rms = (window.iter().map(|s| s * s).sum::<f32>() / FRAME_SIZE as f32).sqrt();
+        if rms < SILENCE_RMS {
+            return (None, 0.0);
+        }
+
+        // Difference function, every lag compares the same number of samples
+        let integration = FRAME_SIZE - self.max_lag;
+        for lag in 1..=self.max_lag {
+            self.difference[lag] = window[..integration]
+                .iter()
+                .zip(&window[lag..lag + integration])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum();
+        }
+
+        // Cumulative mean normalized difference
+        self.difference[0] = 1.0;
+        let mut running_sum = 0.0;
+        for lag in 1..=self.max_lag {
+            running_sum += self.difference[lag];
+            self.difference[lag] = if running_sum > 0.0 {
+                self.difference[lag] * lag as f32 / running_sum
+            } else {
+                1.0
+            };
+        }
+
+        // First dip below the threshold, followed down to its local minimum
+        let mut best = None;
+        let mut lag = self.min_lag.max(2);
+        while lag < self.max_lag {
+            if self.difference[lag] < YIN_THRESHOLD {
+                while lag + 1 < self.max_lag && self.difference[lag + 1] < self.difference[lag] {
+                    lag += 1;
+                }
+                best = Some(lag);
+                break;
+            }
+            lag += 1;
+        }
+
+        let Some(lag) = best else {
+            let minimum = self.difference[self.min_lag.max(2)..self.max_lag]
+                .iter()
+                .cloned()
+                .fold(1.0, f32::min);
+            return (None, (1.0 - minimum).clamp(0.0, 1.0));
+        };
+
+        // Parabolic interpolation for a sub-sample period
+        let (a, b, c) = (
+            self.difference[lag - 1],
+            self.difference[lag],
+            self.difference[lag + 1],
+        );
+        let denominator = a - 2.0 * b + c;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        (
+            Some(self.sample_rate / (lag as f32 + offset)),
+            (1.0 - b).clamp(0.0, 1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn track(samples: &[f32]) -> Vec<PitchFrame> {
+        let mut tracker = PitchTracker::new(SAMPLE_RATE, 2);
+        tracker.process(samples, 0.0)
+    }
+
+    /// Interleaved stereo sine.
+    fn sine(freq: f32, seconds: f32) -> Vec<f32> {
+        let frames = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s =
+                    0.5 * (2.0 * std::f32::consts::PI * freq * i as f32 / SAMPLE_RATE as f32).sin();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_is_reported_within_a_few_cents() {
+        let frames = track(&sine(440.0, 1.0));
+        assert!(frames.len() > 10);
+        // Skip the first windows while the band limiting filters settle
+        for frame in &frames[4..] {
+            let note = frame.midi_note.unwrap();
+            let cents = (note - 69.0) * 100.0;
+            assert!(cents.abs() < 5.0, "{} cents", cents);
+            assert!(frame.confidence > 0.9, "confidence {}", frame.confidence);
+        }
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        let frames = track(&vec![0.0; SAMPLE_RATE as usize * 2]);
+        assert!(!frames.is_empty());
+        assert!(frames
+            .iter()
+            .all(|f| f.frequency_hz.is_none() && f.confidence == 0.0));
+    }
+
+    #[test]
+    fn noise_has_no_pitch() {
+        // Xorshift, so the test doesn't need a rand dependency
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<f32> = (0..SAMPLE_RATE as usize * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+
+        let frames = track(&noise);
+        assert!(!frames.is_empty());
+        assert!(frames.iter().all(|f| f.frequency_hz.is_none()));
+    }
+}
@@ -0,0 +1,28 @@
+/// Tracks where in the current track the captured samples belong, based on
+/// the sample count since the last position the player reported.
+pub struct PlaybackPosition {
+    sample_rate: u32,
+    frames: u64,
+}
+
+impl PlaybackPosition {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            frames: 0,
+        }
+    }
+
+    /// Re-anchors on a position reported by the player.
+    pub fn set_ms(&mut self, position_ms: u32) {
+        self.frames = position_ms as u64 * self.sample_rate as u64 / 1000;
+    }
+
+    pub fn advance(&mut self, frames: usize) {
+        self.frames += frames as u64;
+    }
+
+    pub fn ms(&self) -> f64 {
+        self.frames as f64 * 1000.0 / self.sample_rate as f64
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct SilenceConfig {
+    /// Blocks with an RMS level below this (dBFS) count as silent.
+    pub threshold_db: f32,
+    /// How long the signal must stay below the threshold before
+    /// `silence_started` is emitted.
+    pub hold_ms: u32,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -50.0,
+            hold_ms: 2000,
+        }
+    }
+}
+
+/// Level is measured over blocks of this length.
+const BLOCK_MS: u32 = 50;
+
+/// Payload of the `silence_started` and `silence_ended` events.
+#[derive(Serialize, Clone)]
+pub struct SilenceEvent {
+    /// How long the signal has been silent, in ms of audio.
+    pub duration_ms: u64,
+}
+
+pub enum SilenceOutput {
+    Started(SilenceEvent),
+    Ended(SilenceEvent),
+}
+
+/// Reports runs of near-silent audio, including gaps inside a track.
+pub struct SilenceDetector {
+    channels: usize,
+    block_len: usize,
+    threshold: f32,
+    hold_blocks: u64,
+
+    block_energy: f32,
+    block_fill: usize,
+    /// Consecutive silent blocks in the current run.
+    silent_blocks: u64,
+    /// Whether `silence_started` was emitted without a matching end yet.
+    in_silence: bool,
+}
+
+impl SilenceDetector {
+    pub fn new(config: SilenceConfig, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            block_len: (sample_rate * BLOCK_MS / 1000) as usize,
+            threshold: 10f32.powf(config.threshold_db / 20.0),
+            hold_blocks: (config.hold_ms / BLOCK_MS).max(1) as u64,
+            block_energy: 0.0,
+            block_fill: 0,
+            silent_blocks: 0,
+            in_silence: false,
+        }
+    }
+
+    /// Called when playback pauses or stops. No samples arrive until playback
+    /// resumes, so the current run is dropped rather than counted as silence,
+    /// but an ongoing silence still gets its `silence_ended` later.
+    pub fn interrupt(&mut self) {
+        self.block_energy = 0.0;
+        self.block_fill = 0;
+        if !self.in_silence {
+            self.silent_blocks = 0;
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<SilenceOutput> {
+        let mut outputs = Vec::new();
+
+        for frame in samples.chunks_exact(self.channels) {
+            self.block_energy += frame.iter().map(|s| s * s).sum::<f32>();
+            self.block_fill += 1;
+            if self.block_fill < self.block_len {
+                continue;
+            }
+
+            let rms = (self.block_energy / (self.block_len * self.channels) as f32).sqrt();
+            self.block_energy = 0.0;
+            self.block_fill = 0;
+
+            if rms < self.threshold {
+                self.silent_blocks += 1;
+                if !self.in_silence && self.silent_blocks >= self.hold_blocks {
+                    self.in_silence = true;
+                    outputs.push(SilenceOutput::Started(self.event()));
+                }
+            } else {
+                if self.in_silence {
+                    self.in_silence = false;
+                    outputs.push(SilenceOutput::Ended(self.event()));
+                }
+                self.silent_blocks = 0;
+            }
+        }
+
+        outputs
+    }
+
+    fn event(&self) -> SilenceEvent {
+        SilenceEvent {
+            duration_ms: self.silent_blocks * BLOCK_MS as u64,
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::{str::FromStr, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+
+/// Anything quieter than this is reported as 0 in the quantized band values.
+const DB_FLOOR: f32 = -80.0;
+
+/// Critical band edges (Hz) of the Bark scale, as tabulated by Zwicker.
+const BARK_EDGES: [f32; 25] = [
+    20.0, 100.0, 200.0, 300.0, 400.0, 510.0, 630.0, 770.0, 920.0, 1080.0, 1270.0, 1480.0, 1720.0,
+    2000.0, 2320.0, 2700.0, 3150.0, 3700.0, 4400.0, 5300.0, 6400.0, 7700.0, 9500.0, 12000.0,
+    15500.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandLayout {
+    Linear,
+    Log,
+    Octave,
+    Bark,
+}
+
+impl FromStr for BandLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "linear" => Ok(BandLayout::Linear),
+            "log" => Ok(BandLayout::Log),
+            "octave" => Ok(BandLayout::Octave),
+            "bark" => Ok(BandLayout::Bark),
+            other => Err(format!("unknown band layout \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpectrumConfig {
+    pub layout: BandLayout,
+    /// Number of bands for the linear and log layouts. Octave and bark
+    /// layouts have a fixed set of bands and ignore this.
+    pub bands: usize,
+    pub fft_size: usize,
+    pub hop_size: usize,
+    pub min_freq: f32,
+    pub max_freq: f32,
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self {
+            layout: BandLayout::Log,
+            bands: 32,
+            fft_size: 2048,
+            hop_size: 1024,
+            min_freq: 30.0,
+            max_freq: 16000.0,
+        }
+    }
+}
+
+/// Payload of the `audio_spectrum` event. Band levels are quantized to
+/// 0-255, covering `DB_FLOOR`..0 dBFS, to keep the event small.
+#[derive(Serialize, Clone)]
+pub struct SpectrumFrame {
+    pub layout: BandLayout,
+    pub bands: Vec<u8>,
+}
+
+/// Mono downmix, Hann window and FFT of the hops the spectral analyzers
+/// work on.
+pub struct SpectralWindow {
+    fft_size: usize,
+    hop_size: usize,
+    channels: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    window_gain: f32,
+    /// Downmixed mono samples waiting to be analyzed.
+    pending: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+}
+
+impl SpectralWindow {
+    pub fn new(config: &SpectrumConfig, channels: usize) -> Self {
+        let fft_size = config.fft_size.max(64);
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+
+        // Hann window
+        let window: Vec<f32> = (0..fft_size)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / fft_size as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        let window_gain = window.iter().sum::<f32>();
+
+        Self {
+            fft_size,
+            hop_size: config.hop_size.clamp(1, fft_size),
+            channels: channels.max(1),
+            fft,
+            window,
+            window_gain,
+            pending: Vec::with_capacity(fft_size * 2),
+            scratch: vec![Complex::new(0.0, 0.0); fft_size],
+            magnitudes: vec![0.0; fft_size / 2 + 1],
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Feeds interleaved samples and calls `hop` with the magnitude spectrum
+    /// and the mono samples of every completed window.
+    pub fn process(&mut self, samples: &[f32], mut hop: impl FnMut(&[f32], &[f32])) {
+        self.pending.extend(
+            samples
+                .chunks_exact(self.channels)
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32),
+        );
+
+        while self.pending.len() >= self.fft_size {
+            self.analyze_window();
+            hop(&self.magnitudes, &self.pending[..self.fft_size]);
+            self.pending.drain(..self.hop_size);
+        }
+    }
+
+    fn analyze_window(&mut self) {
+        for ((out, sample), w) in self
+            .scratch
+            .iter_mut()
+            .zip(&self.pending[..self.fft_size])
+            .zip(&self.window)
+        {
+            *out = Complex::new(sample * w, 0.0);
+        }
+        self.fft.process(&mut self.scratch);
+
+        let scale = 2.0 / self.window_gain;
+        for (mag, bin) in self.magnitudes.iter_mut().zip(&self.scratch) {
+            *mag = bin.norm() * scale;
+        }
+    }
+}
+
+pub struct SpectrumAnalyzer {
+    layout: BandLayout,
+    window: SpectralWindow,
+    /// Inclusive FFT bin ranges, one per band.
+    band_bins: Vec<(usize, usize)>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(config: SpectrumConfig, sample_rate: u32, channels: usize) -> Self {
+        let window = SpectralWindow::new(&config, channels);
+        let band_bins = band_edges(&config, sample_rate as f32)
+            .into_iter()
+            .map(|(lo, hi)| bins_for_band(lo, hi, window.fft_size(), sample_rate as f32))
+            .collect();
+
+        Self {
+            layout: config.layout,
+            window,
+            band_bins,
+        }
+    }
+
+    /// Feeds interleaved samples and returns the band levels of every
+    /// completed hop.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<SpectrumFrame> {
+        let mut frames = Vec::new();
+        self.window.process(samples, |magnitudes, _| {
+            frames.push(quantized_frame(self.layout, &self.band_bins, magnitudes))
+        });
+        frames
+    }
+}
+
+fn quantized_frame(
+    layout: BandLayout,
+    band_bins: &[(usize, usize)],
+    magnitudes: &[f32],
+) -> SpectrumFrame {
+    let bands = band_bins
+        .iter()
+        .map(|&(lo, hi)| {
+            let bins = &magnitudes[lo..=hi];
+            let power = bins.iter().map(|m| m * m).sum::<f32>() / bins.len() as f32;
+            let db = 10.0 * power.max(1e-12).log10();
+            ((db - DB_FLOOR) / -DB_FLOOR).clamp(0.0, 1.0) * 255.0
+        })
+        .map(|v| v.round() as u8)
+        .collect();
+
+    SpectrumFrame { layout, bands }
+}
+
+/// Lower and upper frequency of each band for the configured layout.
+fn band_edges(config: &SpectrumConfig, sample_rate: f32) -> Vec<(f32, f32)> {
+    let max_freq = config.max_freq.min(sample_rate / 2.0);
+    let min_freq = config.min_freq.clamp(1.0, max_freq);
+    let bands = config.bands.max(1);
+
+    match config.layout {
+        BandLayout::Linear => {
+            let width = (max_freq - min_freq) / bands as f32;
+            (0..bands)
+                .map(|i| {
+                    let lo = min_freq + width * i as f32;
+                    (lo, lo + width)
+                })
+                .collect()
+        }
+        BandLayout::Log => {
+            let ratio = (max_freq / min_freq).powf(1.0 / bands as f32);
+            (0..bands)
+                .map(|i| {
+                    let lo = min_freq * ratio.powi(i as i32);
+                    (lo, lo * ratio)
+                })
+                .collect()
+        }
+        BandLayout::Octave => {
+            // Standard octave band centres, starting at 31.25 Hz
+            let mut edges = Vec::new();
+            let mut center = 31.25_f32;
+            while center <= max_freq {
+                let (lo, hi) = (center / 2f32.sqrt(), center * 2f32.sqrt());
+                if hi >= min_freq {
+                    edges.push((lo, hi.min(max_freq)));
+                }
+                center *= 2.0;
+            }
+            edges
+        }
+        BandLayout::Bark => BARK_EDGES
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .filter(|&(lo, hi)| hi >= min_freq && lo <= max_freq)
+            .map(|(lo, hi)| (lo, hi.min(max_freq)))
+            .collect(),
+    }
+}
+
+/// Maps a frequency range onto FFT bins. Bands narrower than one bin fall
+/// back to the bin closest to their centre.
+fn bins_for_band(lo: f32, hi: f32, fft_size: usize, sample_rate: f32) -> (usize, usize) {
+    let bin_hz = sample_rate / fft_size as f32;
+    let last_bin = fft_size / 2;
+
+    let lo_bin = ((lo / bin_hz).ceil() as usize).min(last_bin);
+    let hi_bin = ((hi / bin_hz).floor() as usize).min(last_bin);
+    if lo_bin <= hi_bin {
+        (lo_bin, hi_bin)
+    } else {
+        let center = (((lo + hi) / 2.0 / bin_hz).round() as usize).min(last_bin);
+        (center, center)
+    }
+}
@@ -0,0 +1,137 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct StereoConfig {
+    /// Maximum number of `stereo_field` events per second.
+    pub rate_hz: u32,
+    /// Number of goniometer points per event.
+    pub points: usize,
+}
+
+impl Default for StereoConfig {
+    fn default() -> Self {
+        Self {
+            rate_hz: 30,
+            points: 128,
+        }
+    }
+}
+
+/// Payload of the `stereo_field` event, covering one analysis window.
+#[derive(Serialize, Clone)]
+pub struct StereoFieldEvent {
+    /// Pearson correlation of left and right, -1 (phase inverted) to 1 (mono).
+    pub correlation: f32,
+    /// -1 fully left, 0 centred, 1 fully right.
+    pub balance: f32,
+    pub mid_energy: f32,
+    pub side_energy: f32,
+    /// Share of the energy in the side signal, 0 (mono) to 1.
+    pub width: f32,
+    /// Goniometer points as `[side, mid]`, evenly picked from the window.
+    pub points: Vec<[f32; 2]>,
+}
+
+/// Accumulates L/R statistics over windows of `sample_rate / rate_hz`
+/// frames. Only meaningful for stereo input.
+pub struct StereoAnalyzer {
+    config: StereoConfig,
+    channels: usize,
+    window_frames: usize,
+
+    frames: usize,
+    sum_ll: f64,
+    sum_rr: f64,
+    sum_lr: f64,
+    points: Vec<[f32; 2]>,
+}
+
+impl StereoAnalyzer {
+    pub fn new(config: StereoConfig, sample_rate: u32, channels: usize) -> Self {
+        let window_frames = (sample_rate / config.rate_hz.max(1)).max(1) as usize;
+
+        Self {
+            points: Vec::with_capacity(config.points),
+            config: StereoConfig {
+                points: config.points.min(window_frames),
+                ..config
+            },
+            channels,
+            window_frames,
+            frames: 0,
+            sum_ll: 0.0,
+            sum_rr: 0.0,
+            sum_lr: 0.0,
+        }
+    }
+
+    /// Feeds interleaved samples and returns one event per completed window.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<StereoFieldEvent> {
+        if self.channels != 2 {
+            return Vec::new();
+        }
+
+        let point_stride = (self.window_frames / self.config.points.max(1)).max(1);
+        let mut events = Vec::new();
+
+        for frame in samples.chunks_exact(2) {
+            let (left, right) = (frame[0], frame[1]);
+            self.sum_ll += (left * left) as f64;
+            self.sum_rr += (right * right) as f64;
+            self.sum_lr += (left * right) as f64;
+
+            if self.frames % point_stride == 0 && self.points.len() < self.config.points {
+                self.points
+                    .push([(left - right) / 2.0, (left + right) / 2.0]);
+            }
+
+            self.frames += 1;
+            if self.frames == self.window_frames {
+                events.push(self.finish_window());
+            }
+        }
+
+        events
+    }
+
+    fn finish_window(&mut self) -> StereoFieldEvent {
+        let frames = self.frames as f64;
+        let (ll, rr, lr) = (self.sum_ll, self.sum_rr, self.sum_lr);
+
+        let correlation = if ll > 0.0 && rr > 0.0 {
+            (lr / (ll * rr).sqrt()) as f32
+        } else {
+            0.0
+        };
+
+        let (left_rms, right_rms) = ((ll / frames).sqrt(), (rr / frames).sqrt());
+        let balance = if left_rms + right_rms > 0.0 {
+            ((right_rms - left_rms) / (right_rms + left_rms)) as f32
+        } else {
+            0.0
+        };
+
+        // Mean squares of M = (L + R) / 2 and S = (L - R) / 2
+        let mid_energy = ((ll + rr + 2.0 * lr) / 4.0 / frames) as f32;
+        let side_energy = ((ll + rr - 2.0 * lr) / 4.0 / frames) as f32;
+        let width = if mid_energy + side_energy > 0.0 {
+            side_energy / (mid_energy + side_energy)
+        } else {
+            0.0
+        };
+
+        self.frames = 0;
+        self.sum_ll = 0.0;
+        self.sum_rr = 0.0;
+        self.sum_lr = 0.0;
+
+        StereoFieldEvent {
+            correlation: correlation.clamp(-1.0, 1.0),
+            balance,
+            mid_energy,
+            side_energy: side_energy.max(0.0),
+            width: width.clamp(0.0, 1.0),
+            points: std::mem::replace(&mut self.points, Vec::with_capacity(self.config.points)),
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+/// Length of one feature frame. Each frame contributes a chroma vector and
+/// an energy value to the self-similarity analysis.
+const FRAME_MS: u32 = 500;
+const FFT_SIZE: usize = 4096;
+const MIN_FREQ: f32 = 100.0;
+const MAX_FREQ: f32 = 5000.0;
+const DB_FLOOR: f32 = -60.0;
+
+/// Half width of the checkerboard novelty kernel, in frames (8 s).
+const KERNEL_FRAMES: usize = 16;
+/// Boundaries need at least this much normalized novelty, which keeps
+/// steady passages from being split on noise.
+const MIN_NOVELTY: f32 = 0.02;
+/// Sections shorter than this many frames (8 s) are merged away.
+const MIN_SECTION_FRAMES: usize = 16;
+/// Weight of chroma against energy in the frame similarity.
+const CHROMA_WEIGHT: f32 = 0.7;
+/// Sections at least this similar share a label.
+const LABEL_SIMILARITY: f32 = 0.9;
+/// While a track is first heard, the segmentation is redone every this many
+/// new frames (10 s).
+const RESEGMENT_FRAMES: usize = 20;
+
+/// Segmentations are only (re)written when at least this share of the track
+/// has been heard, boundaries from a fragment aren't worth keeping.
+const MIN_SAVE_COVERAGE: f32 = 0.8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    pub start_ms: u32,
+    pub end_ms: u32,
+    /// Sections that sound alike share a label, "A", "B", ... in order of
+    /// first appearance.
+    pub label: String,
+    /// Mean energy of the section relative to the loudest one, 0-1.
+    pub energy: f32,
+}
+
+/// Sections of a whole track, stored as JSON in the app data dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackStructure {
+    pub track_id: String,
+    pub duration_ms: u32,
+    pub sections: Vec<Section>,
+    /// Share of the track the segmentation was computed from.
+    pub coverage: f32,
+}
+
+/// Payload of the `section_changed` event.
+#[derive(Serialize, Clone)]
+pub struct SectionChange {
+    pub track_id: String,
+    pub index: usize,
+    pub section: Section,
+    /// Whether the boundaries come from a stored segmentation. Otherwise they
+    /// were found in the audio heard so far, which confirms a boundary only
+    /// some seconds after it was played.
+    pub stored: bool,
+}
+
+fn structure_path(dir: &Path, track_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", track_id))
+}
+
+pub fn load_structure(dir: &Path, track_id: &str) -> Option<TrackStructure> {
+    let content = fs::read_to_string(structure_path(dir, track_id)).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(structure) => Some(structure),
+        Err(e) => {
+            warn!("Ignoring unreadable structure for {}: {}", track_id, e);
+            None
+        }
+    }
+}
+
+fn save_structure(dir: &Path, structure: &TrackStructure) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let content = serde_json::to_string(structure)?;
+    fs::write(structure_path(dir, &structure.track_id), content)
+}
+
+/// Normalized chroma followed by energy (0-1) of one frame.
+type Features = [f32; 13];
+
+/// Segments the current track into sections from the self-similarity of its
+/// chroma and energy, and reports when playback enters another section.
+pub struct StructureAnalyzer {
+    dir: PathBuf,
+    channels: usize,
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    bin_pitch_class: Vec<Option<usize>>,
+    scratch: Vec<Complex<f32>>,
+
+    track_id: Option<String>,
+    duration_ms: u32,
+    stored: Option<TrackStructure>,
+    /// Segmentation of the audio heard so far.
+    live: Vec<Section>,
+    features: Vec<Option<Features>>,
+    frames_since_segmentation: usize,
+    current: Option<usize>,
+
+    /// Frame being collected: index, sum of squares and sample count. Its
+    /// first mono samples are kept in `frame_samples` for the chroma.
+    frame: Option<(usize, f32, usize)>,
+    frame_samples: Vec<f32>,
+}
+
+impl StructureAnalyzer {
+    pub fn new(dir: PathBuf, sample_rate: u32, channels: usize) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let bin_pitch_class = (0..=FFT_SIZE / 2)
+            .map(|bin| {
+                let freq = bin as f32 * bin_hz;
+                (MIN_FREQ..=MAX_FREQ).contains(&freq).then(|| {
+                    let note = (69.0 + 12.0 * (freq / 440.0).log2()).round() as i32;
+                    note.rem_euclid(12) as usize
+                })
+            })
+            .collect();
+
+        Self {
+            dir,
+            channels: channels.max(1),
+            sample_rate,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            bin_pitch_class,
+            scratch: vec![Complex::new(0.0, 0.0); FFT_SIZE],
+            track_id: None,
+            duration_ms: 0,
+            stored: None,
+            live: Vec::new(),
+            features: Vec::new(),
+            frames_since_segmentation: 0,
+            current: None,
+            frame: None,
+            frame_samples: Vec::with_capacity(FFT_SIZE),
+        }
+    }
+
+    /// Saves the previous track and starts a new one. Returns the stored
+    /// segmentation of the new track, if it was played before.
+    pub fn start_track(&mut self, track_id: String, duration_ms: u32) -> Option<TrackStructure> {
+        self.save();
+
+        self.stored = load_structure(&self.dir, &track_id)
+            .filter(|stored| stored.duration_ms == duration_ms && !stored.sections.is_empty());
+        self.features = vec![None; duration_ms.div_ceil(FRAME_MS) as usize];
+        self.live.clear();
+        self.frames_since_segmentation = 0;
+        self.current = None;
+        self.frame = None;
+        self.track_id = Some(track_id);
+        self.duration_ms = duration_ms;
+        self.stored.clone()
+    }
+
+    /// Segments what was heard of the current track and writes it to disk if
+    /// it covers more of the track than the stored segmentation.
+    pub fn save(&mut self) {
+        let Some(track_id) = self.track_id.clone() else {
+            return;
+        };
+        let filled = self.features.iter().filter(|f| f.is_some()).count();
+        let coverage = filled as f32 / self.features.len().max(1) as f32;
+        let stored_coverage = self.stored.as_ref().map_or(0.0, |s| s.coverage);
+        if coverage < MIN_SAVE_COVERAGE || coverage <= stored_coverage {
+            return;
+        }
+
+        self.segment();
+        let structure = TrackStructure {
+            track_id,
+            duration_ms: self.duration_ms,
+            sections: self.live.clone(),
+            coverage,
+        };
+        match save_structure(&self.dir, &structure) {
+            Ok(()) => info!(
+                "Stored {} sections for {}",
+                structure.sections.len(),
+                structure.track_id
+            ),
+            Err(e) => warn!(
+                "Failed to store structure for {}: {}",
+                structure.track_id, e
+            ),
+        }
+        self.stored = Some(structure);
+    }
+
+    /// Playback jumped, don't mix samples from before and after, and report
+    /// the section at the new position.
+    pub fn seeked(&mut self) {
+        self.frame = None;
+        self.current = None;
+    }
+
+    /// Feeds interleaved samples starting at `position_ms` in the track.
+    /// Returns the new section when playback crossed into one.
+    pub fn process(&mut self, samples: &[f32], position_ms: f64) -> Option<SectionChange> {
+        self.track_id.as_ref()?;
+
+        let frame_ms = 1000.0 / self.sample_rate as f64;
+        for (i, frame) in samples.chunks_exact(self.channels).enumerate() {
+            let mono = frame.iter().sum::<f32>() / self.channels as f32;
+            self.add_sample(position_ms + i as f64 * frame_ms, mono);
+        }
+
+        if self.stored.is_none() && self.frames_since_segmentation >= RESEGMENT_FRAMES {
+            self.frames_since_segmentation = 0;
+            self.segment();
+        }
+
+        let end_ms = position_ms + (samples.len() / self.channels) as f64 * frame_ms;
+        self.section_at(end_ms)
+    }
+
+    fn section_at(&mut self, position_ms: f64) -> Option<SectionChange> {
+        let (sections, stored) = match &self.stored {
+            Some(stored) => (&stored.sections, true),
+            None => (&self.live, false),
+        };
+        let index = sections
+            .iter()
+            .rposition(|section| section.start_ms as f64 <= position_ms)?;
+        if self.current == Some(index) {
+            return None;
+        }
+        self.current = Some(index);
+
+        Some(SectionChange {
+            track_id: self.track_id.clone()?,
+            index,
+            section: sections[index].clone(),
+            stored,
+        })
+    }
+
+    fn add_sample(&mut self, ms: f64, mono: f32) {
+        let index = (ms / FRAME_MS as f64) as usize;
+        if self.frame.is_some_and(|(frame, ..)| frame != index) {
+            self.flush_frame();
+        }
+        let (_, sum_squares, count) = self.frame.get_or_insert_with(|| {
+            self.frame_samples.clear();
+            (index, 0.0, 0)
+        });
+        *sum_squares += mono * mono;
+        *count += 1;
+        // Chroma is taken from the first FFT_SIZE samples of each frame
+        if self.frame_samples.len() < FFT_SIZE {
+            self.frame_samples.push(mono);
+        }
+    }
+
+    fn flush_frame(&mut self) {
+        let Some((index, sum_squares, count)) = self.frame.take() else {
+            return;
+        };
+        // Frames cut short by a seek or track change would skew the energy
+        let full = (self.sample_rate * FRAME_MS / 1000) as usize;
+        if index >= self.features.len()
+            || count < full * 9 / 10
+            || self.frame_samples.len() < FFT_SIZE
+        {
+            return;
+        }
+
+        for ((out, sample), w) in self
+            .scratch
+            .iter_mut()
+            .zip(&self.frame_samples)
+            .zip(&self.window)
+        {
+            *out = Complex::new(sample * w, 0.0);
+        }
+        self.fft.process(&mut self.scratch);
+
+        let mut features: Features = [0.0; 13];
+        for (bin, pitch_class) in self.scratch.iter().zip(&self.bin_pitch_class) {
+            if let Some(pitch_class) = pitch_class {
+                features[*pitch_class] += bin.norm();
+            }
+        }
+        let max = features[..12].iter().cloned().fold(0.0, f32::max);
+        if max > 0.0 {
+            features[..12].iter_mut().for_each(|v| *v /= max);
+        }
+
+        let rms = (sum_squares / count as f32).sqrt();
+        let db = 20.0 * rms.max(1e-6).log10();
+        features[12] = ((db - DB_FLOOR) / -DB_FLOOR).clamp(0.0, 1.0);
+
+        self.features[index] = Some(features);
+        self.frames_since_segmentation += 1;
+    }
+
+    /// Finds boundaries as peaks of a checkerboard-kernel novelty curve over
+    /// the frame self-similarity, then labels recurring sections.
+    fn segment(&mut self) {
+        let novelty = self.novelty();
+        let boundaries = pick_peaks(&novelty);
+
+        let mut sections: Vec<(usize, usize)> = Vec::with_capacity(boundaries.len() + 1);
+        let mut start = 0;
+        for boundary in boundaries.into_iter().chain([self.features.len()]) {
+            if boundary > start {
+                sections.push((start, boundary));
+                start = boundary;
+            }
+        }
+
+        let profiles: Vec<Option<Features>> = sections
+            .iter()
+            .map(|&(start, end)| mean_features(&self.features[start..end]))
+            .collect();
+        let loudest = profiles.iter().flatten().map(|p| p[12]).fold(0.0, f32::max);
+
+        // Each label is represented by the first section that got it
+        let mut representatives: Vec<Features> = Vec::new();
+        self.live = sections
+            .iter()
+            .zip(&profiles)
+            .map(|(&(start, end), profile)| {
+                let label = match profile {
+                    Some(profile) => {
+                        match representatives
+                            .iter()
+                            .position(|r| similarity(r, profile) >= LABEL_SIMILARITY)
+                        {
+                            Some(label) => label,
+                            None => {
+                                representatives.push(*profile);
+                                representatives.len() - 1
+                            }
+                        }
+                    }
+                    None => representatives.len(),
+                };
+
+                Section {
+                    start_ms: start as u32 * FRAME_MS,
+                    end_ms: (end as u32 * FRAME_MS).min(self.duration_ms),
+                    label: section_label(label),
+                    energy: profile.map_or(0.0, |p| p[12] / loudest.max(f32::EPSILON)),
+                }
+            })
+            .collect();
+    }
+
+    /// Novelty of a boundary before each frame. Frames closer than the
+    /// kernel width to unheard audio get none.
+    fn novelty(&self) -> Vec<f32> {
+        let taper: Vec<f32> = (0..KERNEL_FRAMES)
+            .map(|i| {
+                let x = (i as f32 + 0.5) / KERNEL_FRAMES as f32;
+                (-4.0 * x * x).exp()
+            })
+            .collect();
+
+        let kernel_weight = 4.0 * taper.iter().sum::<f32>().powi(2);
+
+        let features = &self.features;
+        let mut novelty = vec![0.0; features.len()];
+        for (i, value) in novelty.iter_mut().enumerate() {
+            if i < KERNEL_FRAMES || i + KERNEL_FRAMES > features.len() {
+                continue;
+            }
+            let heard = features[i - KERNEL_FRAMES..i + KERNEL_FRAMES]
+                .iter()
+                .all(|f| f.is_some());
+            if !heard {
+                continue;
+            }
+
+            let frame = |offset: isize| features[(i as isize + offset) as usize].as_ref().unwrap();
+            let mut sum = 0.0;
+            for (a, wa) in taper.iter().enumerate() {
+                for (b, wb) in taper.iter().enumerate() {
+                    let (past_a, past_b) = (-1 - a as isize, -1 - b as isize);
+                    let (next_a, next_b) = (a as isize, b as isize);
+                    let within = similarity(frame(past_a), frame(past_b))
+                        + similarity(frame(next_a), frame(next_b));
+                    let across = similarity(frame(past_a), frame(next_b))
+                        + similarity(frame(next_a), frame(past_b));
+                    sum += wa * wb * (within - across);
+                }
+            }
+            *value = (sum / kernel_weight).max(0.0);
+        }
+        novelty
+    }
+}
+
+/// Local maxima of the novelty curve that stand out from its mean and are at
+/// least a minimum section length apart, strongest first.
+fn pick_peaks(novelty: &[f32]) -> Vec<usize> {
+    let values: Vec<f32> = novelty.iter().cloned().filter(|&v| v > 0.0).collect();
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let deviation =
+        (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32).sqrt();
+    let threshold = (mean + 0.5 * deviation).max(MIN_NOVELTY);
+
+    let half = MIN_SECTION_FRAMES / 2;
+    let mut candidates: Vec<usize> = (MIN_SECTION_FRAMES..novelty.len().saturating_sub(half))
+        .filter(|&i| {
+            novelty[i] > threshold
+                && novelty[i.saturating_sub(half)..(i + half).min(novelty.len())]
+                    .iter()
+                    .all(|&v| v <= novelty[i])
+        })
+        .collect();
+    candidates.sort_by(|&a, &b| novelty[b].total_cmp(&novelty[a]));
+
+    let mut peaks: Vec<usize> = Vec::new();
+    for candidate in candidates {
+        if peaks
+            .iter()
+            .all(|&peak| peak.abs_diff(candidate) >= MIN_SECTION_FRAMES)
+        {
+            peaks.push(candidate);
+        }
+    }
+    peaks.sort_unstable();
+    peaks
+}
+
+fn mean_features(frames: &[Option<Features>]) -> Option<Features> {
+    let mut sum: Features = [0.0; 13];
+    let mut count = 0;
+    for features in frames.iter().flatten() {
+        sum.iter_mut().zip(features).for_each(|(s, f)| *s += f);
+        count += 1;
+    }
+    (count > 0).then(|| sum.map(|s| s / count as f32))
+}
+
+/// Cosine similarity of the chroma blended with closeness in energy, 0-1.
+fn similarity(a: &Features, b: &Features) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a[..12].iter().zip(&b[..12]) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    let chroma = if norm_a > 0.0 && norm_b > 0.0 {
+        dot / (norm_a * norm_b).sqrt()
+    } else {
+        0.0
+    };
+    let energy = 1.0 - (a[12] - b[12]).abs();
+    CHROMA_WEIGHT * chroma + (1.0 - CHROMA_WEIGHT) * energy
+}
+
+fn section_label(index: usize) -> String {
+    let letter = (b'A' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => letter.to_string(),
+        round => format!("{}{}", letter, round),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16000;
+
+    /// Mono mix of equal amplitude sines.
+    fn chord(freqs: &[f32], amplitude: f32, seconds: u32) -> Vec<f32> {
+        (0..seconds * SAMPLE_RATE)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                freqs
+                    .iter()
+                    .map(|f| (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum::<f32>()
+                    * amplitude
+                    / freqs.len() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boundary_is_found_between_different_sections() {
+        // Never saved to, start_track only looks for a stored segmentation
+        let dir = std::env::temp_dir().join("structure-test-unused");
+        let mut analyzer = StructureAnalyzer::new(dir, SAMPLE_RATE, 1);
+        assert!(analyzer.start_track("track".to_string(), 60_000).is_none());
+
+        // 30 s of a loud C major chord, then 30 s of a quiet F# major one
+        let mut samples = chord(&[261.63, 329.63, 392.0], 0.5, 30);
+        samples.extend(chord(&[369.99, 466.16, 554.37], 0.05, 30));
+
+        let mut changes = Vec::new();
+        for (i, block) in samples.chunks(1600).enumerate() {
+            let position_ms = i as f64 * 100.0;
+            changes.extend(analyzer.process(block, position_ms));
+        }
+
+        let sections = &analyzer.live;
+        assert_eq!(sections.len(), 2, "{:?}", sections);
+        assert!(
+            sections[1].start_ms.abs_diff(30_000) <= 1000,
+            "{:?}",
+            sections
+        );
+        assert_eq!(sections[0].label, "A");
+        assert_eq!(sections[1].label, "B");
+        assert!(sections[0].energy > sections[1].energy);
+
+        let last = changes.last().unwrap();
+        assert_eq!(last.index, 1);
+        assert!(!last.stored);
+    }
+
+    #[test]
+    fn steady_input_is_one_section() {
+        let dir = std::env::temp_dir().join("structure-test-unused");
+        let mut analyzer = StructureAnalyzer::new(dir, SAMPLE_RATE, 1);
+        analyzer.start_track("track".to_string(), 60_000);
+
+        let samples = chord(&[261.63, 329.63, 392.0], 0.5, 60);
+        for (i, block) in samples.chunks(1600).enumerate() {
+            analyzer.process(block, i as f64 * 100.0);
+        }
+
+        assert_eq!(analyzer.live.len(), 1, "{:?}", analyzer.live);
+    }
+}
@@ -1,19 +1,38 @@
 // In the same file as RodioSink, or a related module
 
-use cpal::traits::HostTrait;
-use crossbeam_channel::Sender;
+use cpal::{traits::HostTrait, SupportedBufferSize};
 use librespot::playback::{
     audio_backend::{Sink, SinkError, SinkResult},
     config::AudioFormat,
     convert::Converter,
     decoder::AudioPacket,
+    mixer::VolumeGetter,
     NUM_CHANNELS, SAMPLE_RATE,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use rodio::DeviceTrait;
-use std::{thread, time::Duration};
+use std::{collections::VecDeque, mem, str::FromStr, sync::Arc, time::Duration};
 use thiserror::Error;
 
+use super::analysis::clipping::ClipCounters;
+use super::devices::{OutputDeviceChanged, OutputSwitcher, SinkState};
+use super::latency::{OutputLatency, DEVICE_LATENCY_FALLBACK_MS};
+use super::ring::SampleRing;
+use super::sink_buffer::{SamplePool, SinkBuffer};
+use super::stats::PipelineCounters;
+
+/// Scale of `Converter::f64_to_s16`, which allocates, so the sink converts
+/// into reused buffers itself.
+const SCALE_S16: f64 = 32768.0;
+
+/// Time on top of its queued audio an old output gets to play it out after
+/// switching devices, before it's cut off.
+const SWITCH_DRAIN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long a full sink waits for the output to play anything before the
+/// write fails with a `SinkError`, which makes the player pause.
+const OUTPUT_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Error)]
 pub enum RodioError {
     #[error("<RodioSink> No Device Available")]
@@ -28,108 +47,297 @@ pub enum RodioError {
     DevicesError(#[from] cpal::DevicesError),
 }
 
-type CapturedAudioSample = f32;
+/// Where the visualizer feed is tapped relative to Spotify's software volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTap {
+    /// The player applies the volume, the feed gets quieter with it.
+    PostVolume,
+    /// The sink applies the volume after capturing, so the feed has the same
+    /// level at any listening volume.
+    PreVolume,
+}
+
+impl FromStr for CaptureTap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "post_volume" => Ok(CaptureTap::PostVolume),
+            "pre_volume" => Ok(CaptureTap::PreVolume),
+            other => Err(format!("unknown capture tap \"{}\"", other)),
+        }
+    }
+}
+
+/// Where the sink sends the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkMode {
+    /// An output device, or the null sink if there is none.
+    Device,
+    /// No output, see `NullSink`.
+    Null,
+}
+
+impl FromStr for SinkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "device" => Ok(SinkMode::Device),
+            "null" => Ok(SinkMode::Null),
+            other => Err(format!("unknown sink mode \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub mode: SinkMode,
+    /// Name of the output device, the default one if `None`.
+    pub device: Option<String>,
+    /// Audio queued ahead of the output device. More rides out load spikes,
+    /// less makes pause and volume changes react sooner.
+    pub buffer_ms: u32,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self {
+            mode: SinkMode::Device,
+            device: None,
+            buffer_ms: 500,
+        }
+    }
+}
+
+impl SinkConfig {
+    pub fn buffer(&self) -> Duration {
+        Duration::from_millis(self.buffer_ms as u64)
+    }
+
+    pub fn buffer_frames(&self) -> usize {
+        (self.buffer_ms as u64 * SAMPLE_RATE as u64 / 1000) as usize
+    }
+}
 
 pub struct CaptureRodioSink {
     pub rodio_sink: rodio::Sink,
     pub format: AudioFormat,
-    pub capture_sender: Sender<Vec<CapturedAudioSample>>,
+    pub ring: Arc<SampleRing>,
+    /// Software volume to apply before playback, set for `CaptureTap::PreVolume`.
+    pub volume: Option<Box<dyn VolumeGetter + Send>>,
+    /// Reused for the volume-scaled samples.
+    pub scaled: Vec<f64>,
+    /// Clipped and near-full-scale counts of the samples sent to the device.
+    pub clip_counters: Arc<ClipCounters>,
+    /// Updated with the audio queued on all outputs after every write.
+    pub latency: Arc<OutputLatency>,
+    pub counters: Arc<PipelineCounters>,
+    /// Frames queued in `rodio_sink`, `write` waits while there are more
+    /// than `max_queued_frames`.
+    pub buffer: Arc<SinkBuffer>,
+    pub max_queued_frames: usize,
+    /// Converted samples for the output, reused once played.
+    pub f32_pool: SamplePool<f32>,
+    pub s16_pool: SamplePool<i16>,
+    /// Name of the device `rodio_sink` plays on.
+    pub device_name: Option<String>,
+    pub switcher: OutputSwitcher,
+    /// Outputs switched away from that still play their queue, oldest
+    /// first. `rodio_sink` stays paused until they're done.
+    pub draining: VecDeque<DrainingOutput>,
+    /// Between `start` and `stop`.
+    pub playing: bool,
     pub _stream: rodio::OutputStream,
 }
 
+/// An output switched away from, see `CaptureRodioSink::apply_switch`.
+pub struct DrainingOutput {
+    sink: rodio::Sink,
+    buffer: Arc<SinkBuffer>,
+    _stream: rodio::OutputStream,
+}
+
+impl CaptureRodioSink {
+    pub fn supports(format: AudioFormat) -> bool {
+        matches!(format, AudioFormat::F32 | AudioFormat::S16)
+    }
+
+    /// Moves the output to another device if a switch was requested. The
+    /// new device is opened right away and takes the writes from then on,
+    /// paused until the old one played out its queue, so the position
+    /// carries over without skipping or repeating anything.
+    fn apply_switch(&mut self) {
+        let Some(requested) = self.switcher.take_request() else {
+            return;
+        };
+
+        let output = match create_sink(&cpal::default_host(), requested.clone()) {
+            Ok(output) => output,
+            Err(e) => {
+                self.switcher.report(OutputDeviceChanged {
+                    requested,
+                    device: self.device_name.clone(),
+                    error: Some(e.to_string()),
+                });
+                return;
+            }
+        };
+
+        output.sink.pause();
+        self.draining.push_back(DrainingOutput {
+            sink: mem::replace(&mut self.rodio_sink, output.sink),
+            buffer: mem::replace(&mut self.buffer, Arc::new(SinkBuffer::for_current_thread())),
+            _stream: mem::replace(&mut self._stream, output.stream),
+        });
+        self.latency
+            .set_device(output.name.as_deref(), output.buffer_latency);
+        self.device_name = output.name.clone();
+        self.switcher.report(OutputDeviceChanged {
+            requested,
+            device: output.name,
+            error: None,
+        });
+    }
+
+    /// The output that plays while the sink isn't paused.
+    fn audible(&self) -> &rodio::Sink {
+        self.draining
+            .front()
+            .map_or(&self.rodio_sink, |old| &old.sink)
+    }
+
+    /// Drops the old outputs that played out their queue and starts the
+    /// next one.
+    fn advance_drain(&mut self) {
+        while self
+            .draining
+            .front()
+            .is_some_and(|old| old.buffer.queued_frames() == 0)
+        {
+            self.draining.pop_front();
+            if self.playing {
+                self.audible().play();
+            }
+        }
+    }
+
+    /// Once the paused current output is full, waits for the old ones
+    /// instead, cutting off any that don't finish in time.
+    fn wait_for_drain(&mut self) {
+        while self.buffer.queued_frames() > self.max_queued_frames {
+            let Some(old) = self.draining.front() else {
+                return;
+            };
+            let left = old.buffer.queued(SAMPLE_RATE);
+            if !old.buffer.wait_drained(left + SWITCH_DRAIN_GRACE) {
+                warn!("Old output didn't finish its queued audio, cutting it off");
+                self.draining.pop_front();
+                if self.playing {
+                    self.audible().play();
+                }
+            }
+            self.advance_drain();
+        }
+    }
+
+    /// Audio left to play on all outputs.
+    fn queued(&self) -> Duration {
+        self.draining
+            .iter()
+            .map(|old| old.buffer.queued(SAMPLE_RATE))
+            .sum::<Duration>()
+            + self.buffer.queued(SAMPLE_RATE)
+    }
+
+    /// Buffers not yet played on all outputs.
+    fn queued_buffers(&self) -> usize {
+        self.draining
+            .iter()
+            .map(|old| old.sink.len())
+            .sum::<usize>()
+            + self.rodio_sink.len()
+    }
+}
+
 impl Sink for CaptureRodioSink {
     fn start(&mut self) -> SinkResult<()> {
         debug!("CaptureRodioSink: Start called");
-        self.rodio_sink.play();
+        self.playing = true;
+        self.switcher.set_state(SinkState::Playing);
+        self.apply_switch();
+        self.audible().play();
         Ok(())
     }
 
     fn stop(&mut self) -> SinkResult<()> {
         debug!("CaptureRodioSink: Stop called");
-        self.rodio_sink.pause();
+        self.playing = false;
+        self.switcher.set_state(SinkState::Stopped);
+        self.audible().pause();
+        // Requests sent while it was playing still open the device now
+        self.apply_switch();
         Ok(())
     }
 
     fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        self.apply_switch();
+        self.advance_drain();
+
         // Get original samples (likely f64)
-        let samples = packet
+        let captured = packet
             .samples()
             .map_err(|e| SinkError::OnWrite(format!("CaptureRodioSink Samples Error: {}", e)))?;
 
+        // --- Volume Step (pre-volume tap only) ---
+        let samples = match &self.volume {
+            Some(volume) => {
+                let factor = volume.attenuation_factor();
+                self.scaled.clear();
+                self.scaled.extend(captured.iter().map(|&s| s * factor));
+                &self.scaled[..]
+            }
+            None => captured,
+        };
+        // Counted before the capture step, so the snapshot the capture
+        // thread takes with a chunk covers it
+        self.clip_counters.record(samples);
+
+        // --- Capture Step ---
+        // Taken before any volume the sink applies itself, see `CaptureTap`
+        self.ring.write(captured);
+        self.counters.produced(captured.len());
+
         // This logic is copied & adapted from RodioSink::write
         match self.format {
             AudioFormat::F32 => {
-                let samples_f32: &[f32] = &converter.f64_to_f32(samples);
-
-                // --- Capture Step (f32) ---
-                // Send if the capture format is f32
-                if std::any::TypeId::of::<CapturedAudioSample>() == std::any::TypeId::of::<f32>() {
-                    // SAFETY: We checked the type ID above.
-                    let capture_data = unsafe {
-                        std::mem::transmute::<&[f32], &[CapturedAudioSample]>(samples_f32)
-                    };
-                    match self.capture_sender.try_send(capture_data.to_vec()) {
-                        Ok(_) => {} // Sent for capture
-                        Err(crossbeam_channel::TrySendError::Full(_)) => {}
-                        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
-                            eprintln!("Capture channel disconnected (f32)");
-                        }
-                    }
-                }
+                let mut samples_f32 = self.f32_pool.take(samples.len());
+                samples_f32.extend(samples.iter().map(|&s| s as f32));
+
                 // --- Playback Step (f32) ---
-                let source = rodio::buffer::SamplesBuffer::new(
+                let source = self.buffer.track(
+                    samples_f32,
+                    &self.f32_pool,
                     NUM_CHANNELS as u16,
                     SAMPLE_RATE,
-                    samples_f32,
                 );
                 self.rodio_sink.append(source);
             }
             AudioFormat::S16 => {
-                let samples_s16: &[i16] = &converter.f64_to_s16(samples);
-
-                // --- capture Step i16 ---
-                // Send if the capture format is i16
-                if std::any::TypeId::of::<CapturedAudioSample>() == std::any::TypeId::of::<i16>() {
-                    // SAFETY: ID type checked above.
-                    let capture_data = unsafe {
-                        std::mem::transmute::<&[i16], &[CapturedAudioSample]>(samples_s16)
-                    };
-                    match self.capture_sender.try_send(capture_data.to_vec()) {
-                        Ok(_) => {} // Sent for capture
-                        Err(crossbeam_channel::TrySendError::Full(_)) => {}
-                        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
-                            eprintln!("Capture channel disconnected (i16)");
-                        }
-                    }
-                // --- capture step for f32
-                } else if std::any::TypeId::of::<CapturedAudioSample>()
-                    == std::any::TypeId::of::<f32>()
-                {
-                    let samples_f32_capture: Vec<f32> = samples_s16
+                // Same as `Converter::f64_to_s16`, dithering included
+                let mut samples_s16 = self.s16_pool.take(samples.len());
+                samples_s16.extend(
+                    samples
                         .iter()
-                        .map(|&s| s as f32 / i16::MAX as f32)
-                        .collect();
-
-                    // SAFETY: ID type checked above.
-                    let capture_data = unsafe {
-                        std::mem::transmute::<Vec<f32>, Vec<CapturedAudioSample>>(
-                            samples_f32_capture,
-                        )
-                    };
-                    match self.capture_sender.try_send(capture_data) {
-                        Ok(_) => {} // Sent for capture
-                        Err(crossbeam_channel::TrySendError::Full(_)) => {}
-                        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
-                            eprintln!("Capture channel disconnected (s16->f32)");
-                        }
-                    }
-                }
+                        .map(|&s| converter.scale(s, SCALE_S16) as i16),
+                );
 
                 // --- Playback Step (s16) ---
-                let source = rodio::buffer::SamplesBuffer::new(
+                let source = self.buffer.track(
+                    samples_s16,
+                    &self.s16_pool,
                     NUM_CHANNELS as u16,
                     SAMPLE_RATE,
-                    samples_s16, // Use the original slice
                 );
                 self.rodio_sink.append(source);
             }
@@ -140,18 +348,54 @@ impl Sink for CaptureRodioSink {
             }
         };
 
-        // --- Buffer Management (copied from RodioSink) ---
-        while self.rodio_sink.len() > 26 {
-            thread::sleep(Duration::from_millis(10));
+        // --- Buffer Management ---
+        // Woken by the outputs as they finish buffers, see `SinkBuffer`
+        self.wait_for_drain();
+        if !self
+            .buffer
+            .wait_for_room(self.max_queued_frames, OUTPUT_STALL_TIMEOUT)
+        {
+            return Err(SinkError::OnWrite(format!(
+                "CaptureRodioSink: output played nothing for {:?}",
+                OUTPUT_STALL_TIMEOUT
+            )));
         }
+        self.latency.set_queued(self.queued());
+        self.counters.set_sink_queue(self.queued_buffers());
         Ok(())
     }
 }
 
-pub fn create_sink(
-    host: &cpal::Host,
-    device: Option<String>,
-) -> Result<(rodio::Sink, rodio::OutputStream), RodioError> {
+/// An opened output device.
+pub struct OpenOutput {
+    pub sink: rodio::Sink,
+    pub stream: rodio::OutputStream,
+    pub name: Option<String>,
+    /// See `buffer_latency`.
+    pub buffer_latency: Option<Duration>,
+}
+
+/// Latency of the device's buffer, as far as cpal reports it. rodio opens
+/// the stream with the host's default buffer size, which cpal doesn't tell,
+/// so this is the fallback estimate kept within the sizes the device
+/// supports. That is exact for devices with a fixed buffer size.
+fn buffer_latency(device: &cpal::Device) -> Option<Duration> {
+    let config = device.default_output_config().ok()?;
+    let SupportedBufferSize::Range { min, max } = *config.buffer_size() else {
+        return None;
+    };
+    if min > max {
+        return None;
+    }
+
+    let sample_rate = config.sample_rate().0 as u64;
+    let fallback_frames = DEVICE_LATENCY_FALLBACK_MS as u64 * sample_rate / 1000;
+    let frames = fallback_frames.clamp(min as u64, max as u64);
+    Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64))
+}
+
+/// Opens the named or the default output device.
+pub fn create_sink(host: &cpal::Host, device: Option<String>) -> Result<OpenOutput, RodioError> {
     let rodio_device = match device.as_deref() {
         Some(device_name) => {
             host.output_devices()?
@@ -171,5 +415,10 @@ pub fn create_sink(
 
     let (stream, handle) = rodio::OutputStream::try_from_device(&rodio_device)?;
     let sink = rodio::Sink::try_new(&handle)?;
-    Ok((sink, stream))
+    Ok(OpenOutput {
+        sink,
+        stream,
+        name,
+        buffer_latency: buffer_latency(&rodio_device),
+    })
 }
@@ -1,3 +1,5 @@
+use std::{path::Path, str::FromStr};
+
 use data_encoding::HEXLOWER;
 use librespot::{
     connect::ConnectConfig,
@@ -9,6 +11,15 @@ use librespot::{
 };
 use sha1::{Digest, Sha1};
 
+use super::analysis::{
+    gain::AutoGainConfig, pipeline::PipelineConfig, pitch::PitchConfig, silence::SilenceConfig,
+    spectrum::SpectrumConfig, stereo::StereoConfig, AnalysisConfig,
+};
+use super::captured_rodio_sink::{CaptureTap, SinkConfig};
+use super::devices::{configured_output_device, configured_output_offsets};
+use super::latency::LatencyConfig;
+use super::reframer::FeedConfig;
+
 pub struct SpotifyConfig {
     pub device_name: String,
 
@@ -17,14 +28,107 @@ pub struct SpotifyConfig {
     pub session: SessionConfig,
     pub connect: ConnectConfig,
     pub mixer: MixerConfig,
+    pub capture_tap: CaptureTap,
+    pub analysis: AnalysisConfig,
+    pub feed: FeedConfig,
+    pub latency: LatencyConfig,
+    pub sink: SinkConfig,
 }
 
 fn device_id(name: &str) -> String {
     HEXLOWER.encode(&Sha1::digest(name.as_bytes()))
 }
 
+/// Reads a setting from the app config, falling back to `default` when the
+/// key is missing or its value doesn't parse.
+fn setting<T: FromStr>(app_dir: &Path, key: &str, default: T) -> T {
+    match crate::read_config(app_dir, key.to_string()) {
+        Ok(Some(value)) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Ignoring invalid value \"{}\" for setting {}", value, key);
+            default
+        }),
+        _ => default,
+    }
+}
+
+fn analysis_config(app_dir: &Path) -> AnalysisConfig {
+    let spectrum = SpectrumConfig::default();
+    let stereo = StereoConfig::default();
+    let auto_gain = AutoGainConfig::default();
+    let silence = SilenceConfig::default();
+    let pitch = PitchConfig::default();
+    let pipeline = PipelineConfig::default();
+
+    AnalysisConfig {
+        data_dir: app_dir.to_path_buf(),
+        spectrum: SpectrumConfig {
+            layout: setting(app_dir, "spectrum_layout", spectrum.layout),
+            bands: setting(app_dir, "spectrum_bands", spectrum.bands),
+            fft_size: setting(app_dir, "spectrum_fft_size", spectrum.fft_size),
+            hop_size: setting(app_dir, "spectrum_hop_size", spectrum.hop_size),
+            min_freq: setting(app_dir, "spectrum_min_freq", spectrum.min_freq),
+            max_freq: setting(app_dir, "spectrum_max_freq", spectrum.max_freq),
+        },
+        stereo: StereoConfig {
+            rate_hz: setting(app_dir, "stereo_rate_hz", stereo.rate_hz),
+            points: setting(app_dir, "stereo_points", stereo.points),
+        },
+        auto_gain: AutoGainConfig {
+            enabled: setting(app_dir, "agc_enabled", auto_gain.enabled),
+            target_db: setting(app_dir, "agc_target_db", auto_gain.target_db),
+            attack_ms: setting(app_dir, "agc_attack_ms", auto_gain.attack_ms),
+            release_ms: setting(app_dir, "agc_release_ms", auto_gain.release_ms),
+            max_gain_db: setting(app_dir, "agc_max_gain_db", auto_gain.max_gain_db),
+            min_gain_db: setting(app_dir, "agc_min_gain_db", auto_gain.min_gain_db),
+        },
+        silence: SilenceConfig {
+            threshold_db: setting(app_dir, "silence_threshold_db", silence.threshold_db),
+            hold_ms: setting(app_dir, "silence_hold_ms", silence.hold_ms),
+        },
+        pitch: PitchConfig {
+            enabled: setting(app_dir, "pitch_enabled", pitch.enabled),
+        },
+        pipeline: PipelineConfig {
+            disabled: setting(app_dir, "analyzers_disabled", String::new())
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            cpu_budget: setting(app_dir, "analyzer_cpu_budget", pipeline.cpu_budget),
+        },
+    }
+}
+
+fn feed_config(app_dir: &Path) -> FeedConfig {
+    let feed = FeedConfig::default();
+
+    FeedConfig {
+        frame_size: setting(app_dir, "feed_frame_size", feed.frame_size),
+        rate_hz: setting(app_dir, "feed_rate_hz", feed.rate_hz),
+        downmix: setting(app_dir, "feed_downmix", feed.downmix),
+        decimation: setting(app_dir, "feed_decimation", feed.decimation),
+    }
+}
+
+fn sink_config(app_dir: &Path) -> SinkConfig {
+    let sink = SinkConfig::default();
+
+    SinkConfig {
+        mode: setting(app_dir, "sink_mode", sink.mode),
+        device: configured_output_device(app_dir),
+        buffer_ms: setting(app_dir, "sink_buffer_ms", sink.buffer_ms),
+    }
+}
+
+fn latency_config(app_dir: &Path) -> LatencyConfig {
+    LatencyConfig {
+        device_offsets_ms: configured_output_offsets(app_dir),
+    }
+}
+
 impl SpotifyConfig {
-    pub fn new(display_name: &str) -> Self {
+    pub fn new(display_name: &str, app_dir: &Path) -> Self {
         let device_id = device_id(display_name);
 
         Self {
@@ -47,6 +151,18 @@ impl SpotifyConfig {
                 ..ConnectConfig::default()
             },
             mixer: MixerConfig::default(),
+            capture_tap: setting(app_dir, "capture_tap", CaptureTap::PostVolume),
+            analysis: analysis_config(app_dir),
+            feed: feed_config(app_dir),
+            latency: latency_config(app_dir),
+            sink: sink_config(app_dir),
         }
     }
+
+    /// Re-reads the settings the capture pipeline is built from.
+    pub fn reload_capture(&mut self) {
+        let app_dir = self.analysis.data_dir.clone();
+        self.analysis = analysis_config(&app_dir);
+        self.feed = feed_config(&app_dir);
+    }
 }
@@ -9,16 +9,22 @@ use librespot::{
     connect::Spirc,
     core::{cache::Cache, Session},
     discovery::Credentials,
-    playback::{audio_backend::Sink, config::AudioFormat, mixer, player::Player},
+    playback::{
+        mixer::{self, NoOpVolume, VolumeGetter},
+        player::Player,
+    },
 };
-use log::error;
+use log::{error, info, warn};
 use tauri::AppHandle;
 use tokio::task::JoinHandle;
 
 use super::{
+    captured_rodio_sink::{CaptureRodioSink, CaptureTap},
     config::SpotifyConfig,
+    devices::{self, OutputSwitch},
     event_handler,
-    setup::{init_capture_channel, mk_capture_rodio_for_fn_ptr},
+    latency::OutputLatency,
+    setup::{mk_capture_rodio, CaptureChannel, CapturePipeline},
 };
 
 const RECONNECT_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(600);
@@ -43,20 +49,41 @@ pub struct SpotifyCore {
     pub last_credentials: Option<Credentials>,
     pub auto_connect_times: Vec<Instant>,
     player_event_handle: Option<JoinHandle<()>>,
+    /// Asks the sink to move to another output device.
+    pub output_switch: OutputSwitch,
+
+    handle: Box<AppHandle>,
+    capture_channel: CaptureChannel,
+    capture: CapturePipeline,
 }
 
 impl SpotifyCore {
-    pub async fn new(config: SpotifyConfig, handle: Box<AppHandle>) -> Self {
-        let cache = Cache::new(Some(CACHE), Some(CACHE), Some(CACHE_FILES), None)
-            .expect("could not create cache");
-
-        if let Err(e) = init_capture_channel(handle.clone()) {
-            error!("Failed to initialize capture channel: {}", e);
-            panic!("Failed to initialize capture channel: {}", e);
+    pub async fn new(config: SpotifyConfig, handle: Box<AppHandle>) -> Result<Self, String> {
+        if !CaptureRodioSink::supports(config.audio_format) {
+            return Err(format!(
+                "Unsupported audio format {:?}, only F32 and S16 can be played",
+                config.audio_format
+            ));
         }
 
-        let backend_builder: fn(Option<String>, AudioFormat) -> Box<dyn Sink> =
-            mk_capture_rodio_for_fn_ptr;
+        let cache = Cache::new(Some(CACHE), Some(CACHE), Some(CACHE_FILES), None)
+            .map_err(|e| format!("Could not create cache: {}", e))?;
+
+        let capture_channel = CaptureChannel::default();
+        capture_channel
+            .latency
+            .set_offsets(config.latency.device_offsets_ms.clone());
+        let capture = CapturePipeline::start(
+            &capture_channel,
+            (*handle).clone(),
+            config.analysis.clone(),
+            config.feed.clone(),
+            Vec::new(),
+        )
+        .map_err(|e| {
+            error!("Failed to start capture pipeline: {}", e);
+            e
+        })?;
 
         let mixer_builder = mixer::find(None).unwrap(); // Get the builder
         let mixer_instance = mixer_builder(config.mixer.clone()); // Create Arc'd instance ONCE
@@ -82,19 +109,45 @@ impl SpotifyCore {
         let sink_app_handle = handle.clone();
         let sink_callback = event_handler::create_sink_event_callback(sink_app_handle);
 
+        // With the pre-volume tap the sink applies the soft volume after
+        // capturing, so the player must not apply it as well.
+        let (player_volume, sink_volume): (Box<dyn VolumeGetter + Send>, _) = match config
+            .capture_tap
+        {
+            CaptureTap::PostVolume => (mixer_instance.get_soft_volume(), None),
+            CaptureTap::PreVolume => (Box::new(NoOpVolume), Some(mixer_instance.get_soft_volume())),
+        };
+
+        let audio_format = config.audio_format;
+        let sink_capture = capture_channel.clone();
+        let sink_config = config.sink.clone();
+        let (output_switch, switcher) = devices::output_switch((*handle).clone());
         let player = Player::new(
             config.player.clone(),
             session.clone(),
-            mixer_instance.get_soft_volume(),
-            move || (backend_builder)(None, config.audio_format.clone()),
+            player_volume,
+            move || {
+                mk_capture_rodio(
+                    sink_config.device.clone(),
+                    audio_format,
+                    sink_volume,
+                    &sink_capture,
+                    &sink_config,
+                    switcher,
+                )
+            },
         );
 
         player.set_sink_event_callback(Some(sink_callback));
         let player_events = player.get_player_event_channel();
-        let event_listener_handle =
-            event_handler::spawn_player_event_listener(player_events, handle.clone());
+        let event_listener_handle = event_handler::spawn_player_event_listener(
+            player_events,
+            handle.clone(),
+            capture_channel.control.clone(),
+            capture_channel.latency.clone(),
+        );
 
-        Self {
+        Ok(Self {
             session,
             player,
             discovery,
@@ -107,6 +160,41 @@ impl SpotifyCore {
             last_credentials: None,
             auto_connect_times: vec![],
             player_event_handle: Some(event_listener_handle),
+            output_switch,
+            handle,
+            capture_channel,
+            capture,
+        })
+    }
+
+    /// Replaces the capture pipeline with one built from the current config,
+    /// keeping the track and position. Playback isn't interrupted.
+    pub fn restart_capture(&mut self) -> Result<(), String> {
+        let resume = self.capture.stop();
+        self.capture = CapturePipeline::start(
+            &self.capture_channel,
+            (*self.handle).clone(),
+            self.config.analysis.clone(),
+            self.config.feed.clone(),
+            resume,
+        )?;
+        Ok(())
+    }
+
+    /// Shared with the sink, which keeps it up to date.
+    pub fn output_latency(&self) -> Arc<OutputLatency> {
+        self.capture_channel.latency.clone()
+    }
+
+    /// Restarts the capture pipeline if its thread died.
+    pub fn ensure_capture_running(&mut self) {
+        if self.capture.is_running() {
+            return;
+        }
+
+        warn!("Capture pipeline stopped unexpectedly, restarting");
+        if let Err(e) = self.restart_capture() {
+            error!("Failed to restart capture pipeline: {}", e);
         }
     }
 
@@ -116,7 +204,7 @@ impl SpotifyCore {
 
         if let Some(spirc) = self.spirc.take() {
             if let Err(e) = spirc.shutdown() {
-                println!("[ERROR] error sending spirc shutdown message: {}", e);
+                error!("Error sending spirc shutdown message: {}", e);
             }
         }
         if let Some(spirc_task) = self.spirc_task.take() {
@@ -151,7 +239,7 @@ impl SpotifyCore {
         let (spirc_, spirc_task_) = match spirc_result {
             Ok((spirc_, spirc_task_)) => (spirc_, spirc_task_),
             Err(e) => {
-                println!("[ERROR] could not initialize spirc: {}", e);
+                error!("Could not initialize spirc: {}", e);
                 self.connecting = false;
                 self.last_credentials = None; // Clear credentials
                 return Err(());
@@ -160,7 +248,7 @@ impl SpotifyCore {
 
         self.spirc = Some(spirc_);
         self.spirc_task = Some(Box::pin(spirc_task_));
-        println!("[INFO] connected to spotify");
+        info!("Connected to Spotify");
 
         let token = self
             .session
@@ -191,16 +279,17 @@ impl SpotifyCore {
 
             self.connecting = true;
         }
-        println!("[INFO] disconnecting from spotify");
+        info!("Disconnecting from Spotify");
     }
 
     pub async fn shutdown(&mut self) {
-        log::info!("Shutting down SpotifyCore...");
+        info!("Shutting down SpotifyCore...");
         // Abort the event listener task
         if let Some(handle) = self.player_event_handle.take() {
             log::debug!("Aborting player event listener task...");
             handle.abort();
         }
-        log::info!("SpotifyCore shutdown complete.");
+        self.capture.stop();
+        info!("SpotifyCore shutdown complete.");
     }
 }
@@ -0,0 +1,317 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{error, info, warn};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, State};
+
+use super::SpotifyHandle;
+
+/// Config key of the output device the sink opens, the default device if
+/// unset or empty.
+pub const OUTPUT_DEVICE_KEY: &str = "output_device";
+
+/// Config key of the manual latency corrections, a JSON object of device
+/// name to milliseconds. ALSA device names contain `=`, so they can't be
+/// part of a key themselves.
+pub const OUTPUT_OFFSETS_KEY: &str = "output_offsets_ms";
+
+pub const OUTPUT_DEVICE_CHANGED_EVENT: &str = "output_device_changed";
+
+/// A stream configuration range the device supports.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+    /// Whether this is the device in the config.
+    pub is_selected: bool,
+    /// Manual latency correction, see `set_output_latency_offset`.
+    pub latency_offset_ms: Option<i64>,
+    pub configs: Vec<OutputConfig>,
+}
+
+/// The configured output device, `None` for the default one.
+pub fn configured_output_device(app_dir: &Path) -> Option<String> {
+    crate::read_config(app_dir, OUTPUT_DEVICE_KEY.to_string())
+        .ok()
+        .flatten()
+        .filter(|name| !name.is_empty())
+}
+
+/// The manual latency corrections by device name.
+pub fn configured_output_offsets(app_dir: &Path) -> HashMap<String, i64> {
+    let Some(offsets) = crate::read_config(app_dir, OUTPUT_OFFSETS_KEY.to_string())
+        .ok()
+        .flatten()
+        .filter(|offsets| !offsets.is_empty())
+    else {
+        return HashMap::new();
+    };
+
+    serde_json::from_str(&offsets).unwrap_or_else(|e| {
+        warn!("Ignoring invalid {}: {}", OUTPUT_OFFSETS_KEY, e);
+        HashMap::new()
+    })
+}
+
+fn supported_configs(device: &cpal::Device) -> Vec<OutputConfig> {
+    match device.supported_output_configs() {
+        Ok(configs) => configs
+            .map(|config| OutputConfig {
+                channels: config.channels(),
+                min_sample_rate: config.min_sample_rate().0,
+                max_sample_rate: config.max_sample_rate().0,
+                sample_format: config.sample_format().to_string(),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Cannot get the configs of an output device: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// The output devices of the default host. Devices whose name can't be read
+/// are left out, since they couldn't be selected.
+#[tauri::command]
+pub fn list_output_devices(
+    state: State<'_, crate::AppConfigState>,
+) -> Result<Vec<OutputDevice>, String> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let selected = configured_output_device(&state.app_dir);
+    let offsets = configured_output_offsets(&state.app_dir);
+
+    let devices = host
+        .output_devices()
+        .map_err(|e| format!("Cannot get audio devices: {}", e))?;
+
+    Ok(devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            Some(OutputDevice {
+                is_default: default_name.as_ref() == Some(&name),
+                is_selected: selected.as_ref() == Some(&name),
+                latency_offset_ms: offsets.get(&name).copied(),
+                configs: supported_configs(&device),
+                name,
+            })
+        })
+        .collect())
+}
+
+/// Payload of `output_device_changed`, emitted when a switch finished or
+/// failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputDeviceChanged {
+    /// The device asked for, `None` for the default one.
+    pub requested: Option<String>,
+    /// The device the audio goes to now, it's audible once the old one
+    /// played out its queue. On failure, the one that kept playing.
+    pub device: Option<String>,
+    pub error: Option<String>,
+}
+
+/// What the sink is doing, so a switch request gets an answer while the
+/// player thread isn't picking requests up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkState {
+    /// Paused or nothing loaded yet.
+    Stopped,
+    Playing,
+    /// The null sink, which has no device to switch. `configured` if the
+    /// sink mode asks for it, otherwise no output device could be opened.
+    Null {
+        configured: bool,
+    },
+}
+
+/// Why the null sink can't switch devices. The device is saved either way.
+pub fn null_sink_reason(configured: bool) -> String {
+    if configured {
+        "The sink mode is null, so no device is open. The saved device is used when the app starts with the device mode".into()
+    } else {
+        "No output device could be opened when Spotify started, so none is open. The saved device is used the next time the app starts".into()
+    }
+}
+
+/// How `switch_output_device` went on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchOutcome {
+    /// The player thread is switching, `output_device_changed` follows.
+    Switching,
+    /// Playback is paused or stopped, the device is opened when it starts
+    /// and `output_device_changed` is emitted then.
+    OnResume,
+}
+
+/// Creates both ends of output device switches for a sink.
+pub fn output_switch(app_handle: AppHandle) -> (OutputSwitch, OutputSwitcher) {
+    let (requests, receiver) = unbounded();
+    let state = Arc::new(Mutex::new(SinkState::Stopped));
+    let switch = OutputSwitch {
+        requests,
+        state: state.clone(),
+    };
+    let switcher = OutputSwitcher {
+        requests: receiver,
+        state,
+        app_handle,
+    };
+    (switch, switcher)
+}
+
+/// The commands' end of output device switches.
+#[derive(Clone)]
+pub struct OutputSwitch {
+    requests: Sender<Option<String>>,
+    state: Arc<Mutex<SinkState>>,
+}
+
+impl OutputSwitch {
+    /// Passes the request to the sink, or refuses it right away if the sink
+    /// has no device to switch.
+    pub fn request(&self, name: Option<String>) -> Result<SwitchOutcome, String> {
+        let state = *self.state.lock().unwrap();
+        if let SinkState::Null { configured } = state {
+            return Err(null_sink_reason(configured));
+        }
+
+        self.requests
+            .send(name)
+            .map_err(|_| "The player is not running".to_string())?;
+        Ok(match state {
+            SinkState::Playing => SwitchOutcome::Switching,
+            _ => SwitchOutcome::OnResume,
+        })
+    }
+}
+
+/// The sink's end of output device switches. Requests are picked up by the
+/// player thread, which owns the output stream.
+pub struct OutputSwitcher {
+    requests: Receiver<Option<String>>,
+    state: Arc<Mutex<SinkState>>,
+    app_handle: AppHandle,
+}
+
+impl OutputSwitcher {
+    /// The latest pending request, earlier ones are superseded.
+    pub fn take_request(&self) -> Option<Option<String>> {
+        self.requests.try_iter().last()
+    }
+
+    /// Only called on start and stop, never on the write path.
+    pub fn set_state(&self, state: SinkState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn report(&self, change: OutputDeviceChanged) {
+        match &change.error {
+            None => info!("Switched output to {:?}", change.device),
+            Some(e) => warn!("Switching output to {:?} failed: {}", change.requested, e),
+        }
+        if let Err(e) = self.app_handle.emit(OUTPUT_DEVICE_CHANGED_EVENT, change) {
+            error!("Failed to emit {}: {}", OUTPUT_DEVICE_CHANGED_EVENT, e);
+        }
+    }
+}
+
+/// Checks that the device exists and stores it as the one to open.
+fn store_output_device(app_dir: &Path, name: &Option<String>) -> Result<(), String> {
+    if let Some(name) = name {
+        let host = cpal::default_host();
+        let exists = host
+            .output_devices()
+            .map_err(|e| format!("Cannot get audio devices: {}", e))?
+            .any(|device| device.name().ok().as_ref() == Some(name));
+        if !exists {
+            return Err(format!("Output device \"{}\" not found", name));
+        }
+    }
+
+    info!(
+        "Output device set to {}",
+        name.as_deref().unwrap_or("the default device")
+    );
+    crate::write_config(
+        &app_dir.join("data.txt"),
+        OUTPUT_DEVICE_KEY.to_string(),
+        name.clone().unwrap_or_default(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Stores the output device the sink opens from now on, `None` for the
+/// default device.
+#[tauri::command]
+pub fn set_output_device(
+    state: State<'_, crate::AppConfigState>,
+    name: Option<String>,
+) -> Result<(), String> {
+    store_output_device(&state.app_dir, &name)
+}
+
+/// Stores the output device and moves the running playback to it. The
+/// player thread opens the new device right away and writes to it while the
+/// old one plays out its queue, so nothing is skipped or repeated. While
+/// paused that happens when playback resumes, which the returned outcome
+/// tells. `output_device_changed` reports the result.
+#[tauri::command]
+pub fn switch_output_device(
+    state: State<'_, crate::AppConfigState>,
+    spotify: State<'_, SpotifyHandle>,
+    name: Option<String>,
+) -> Result<SwitchOutcome, String> {
+    store_output_device(&state.app_dir, &name)?;
+
+    let switch = spotify.switch_output.lock().unwrap().clone();
+    match switch {
+        Some(switch) => switch.request(name),
+        None => Err("Spotify is not running".to_string()),
+    }
+}
+
+/// Stores a manual latency correction for an output device, added to the
+/// estimated latency the visuals and positions are delayed by. `None`
+/// removes it. Applies right away if the device is playing.
+#[tauri::command]
+pub fn set_output_latency_offset(
+    state: State<'_, crate::AppConfigState>,
+    spotify: State<'_, SpotifyHandle>,
+    device: String,
+    offset_ms: Option<i64>,
+) -> Result<(), String> {
+    let mut offsets = configured_output_offsets(&state.app_dir);
+    match offset_ms {
+        Some(offset_ms) => offsets.insert(device.clone(), offset_ms),
+        None => offsets.remove(&device),
+    };
+
+    let offsets = serde_json::to_string(&offsets).map_err(|e| e.to_string())?;
+    crate::write_config(
+        &state.app_dir.join("data.txt"),
+        OUTPUT_OFFSETS_KEY.to_string(),
+        offsets,
+    )
+    .map_err(|e| e.to_string())?;
+
+    if let Some(latency) = spotify.latency.lock().unwrap().as_ref() {
+        latency.set_offset(&device, offset_ms);
+    }
+    Ok(())
+}
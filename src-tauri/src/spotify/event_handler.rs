@@ -1,8 +1,13 @@
+use crossbeam_channel::Sender;
 use log::{error, warn};
 use serde::Serialize;
 use tauri::{AppHandle, Emitter};
 use tokio::task::JoinHandle;
 
+use std::sync::Arc;
+
+use super::{analysis::AnalysisControl, latency::OutputLatency};
+
 use librespot::{
     metadata::audio::UniqueFields,
     playback::player::{PlayerEvent, PlayerEventChannel, SinkStatus},
@@ -110,6 +115,8 @@ const TAURI_PLAYER_EVENT: &str = "spotify_player_event";
 pub fn spawn_player_event_listener(
     mut player_events: PlayerEventChannel,
     app_handle: Box<AppHandle>,
+    analysis_control: Sender<AnalysisControl>,
+    latency: Arc<OutputLatency>,
 ) -> JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
         log::info!("Spotify PlayerEvent listener thread started.");
@@ -123,7 +130,11 @@ pub fn spawn_player_event_listener(
                     log::trace!("Received PlayerEvent: {:?}", event);
                     let potential_payload = map_player_event_to_payload(event);
 
-                    if let Some(payload) = potential_payload {
+                    if let Some(mut payload) = potential_payload {
+                        // The analysis delays the control itself, the
+                        // webview gets the position that is audible now
+                        forward_to_analysis(&payload, &analysis_control);
+                        compensate_latency(&mut payload, &latency);
                         if let Err(e) = app_handle.emit(TAURI_PLAYER_EVENT, payload) {
                             error!("Failed to emit Tauri player event: {}", e);
                         }
@@ -135,6 +146,44 @@ pub fn spawn_player_event_listener(
     })
 }
 
+fn forward_to_analysis(
+    payload: &SpotifyPlayerEventPayload,
+    analysis_control: &Sender<AnalysisControl>,
+) {
+    let control = match payload {
+        SpotifyPlayerEventPayload::TrackChanged { item } => AnalysisControl::TrackChanged {
+            track_id: item.track_id.clone(),
+            duration_ms: item.duration_ms,
+        },
+        SpotifyPlayerEventPayload::Playing { position_ms, .. } => AnalysisControl::Playing {
+            position_ms: *position_ms,
+        },
+        SpotifyPlayerEventPayload::Seeked { position_ms, .. } => AnalysisControl::Seeked {
+            position_ms: *position_ms,
+        },
+        SpotifyPlayerEventPayload::Paused { .. } => AnalysisControl::Paused,
+        SpotifyPlayerEventPayload::Stopped { .. } => AnalysisControl::Stopped,
+        _ => return,
+    };
+
+    if analysis_control.send(control).is_err() {
+        warn!("Analysis thread is gone, dropping player event");
+    }
+}
+
+/// Moves reported positions back by the output latency. The player reports
+/// the position of the samples it is writing, which are heard later.
+fn compensate_latency(payload: &mut SpotifyPlayerEventPayload, latency: &OutputLatency) {
+    match payload {
+        SpotifyPlayerEventPayload::Playing { position_ms, .. }
+        | SpotifyPlayerEventPayload::Paused { position_ms, .. }
+        | SpotifyPlayerEventPayload::Seeked { position_ms, .. } => {
+            *position_ms = latency.audible_position_ms(*position_ms);
+        }
+        _ => {}
+    }
+}
+
 fn map_player_event_to_payload(event: PlayerEvent) -> Option<SpotifyPlayerEventPayload> {
     match event {
         PlayerEvent::TrackChanged { audio_item } => match audio_item.track_id.to_base62() {
@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{
+    ipc::{Channel, InvokeResponseBody},
+    State,
+};
+
+/// Size of the header in front of the samples of a binary chunk.
+const HEADER_BYTES: usize = 24;
+
+/// Sample encoding of the binary audio feed. Samples are interleaved and
+/// little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedFormat {
+    F32,
+    I16,
+}
+
+impl FeedFormat {
+    /// Id of the format in the binary header.
+    fn id(self) -> u8 {
+        match self {
+            FeedFormat::F32 => 0,
+            FeedFormat::I16 => 1,
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            FeedFormat::F32 => 4,
+            FeedFormat::I16 => 2,
+        }
+    }
+}
+
+/// A chunk of the visualizer feed. This is the payload of the JSON
+/// `audio_chunk` event, where `format` is always `f32`.
+#[derive(Serialize, Clone)]
+pub struct AudioChunk {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub format: FeedFormat,
+    /// Index of the chunk's first frame among all frames of the feed. It only
+    /// increases; consecutive chunks overlap or leave a gap depending on the
+    /// feed rate.
+    pub sample_index: u64,
+    /// Track position of the chunk's first frame, comparable with the
+    /// `Playing` and `Seeked` positions.
+    pub position_ms: f64,
+    /// Interleaved samples.
+    pub samples: Vec<f32>,
+}
+
+impl AudioChunk {
+    /// Binary form sent over IPC channels: a header of sample rate (u32),
+    /// channels (u16), format id (u8, 0 = f32, 1 = i16), a reserved byte,
+    /// sample index (u64) and position in ms (f64), followed by the samples,
+    /// all little-endian.
+    fn encode(&self, format: FeedFormat) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_BYTES + self.samples.len() * format.bytes_per_sample());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.channels as u16).to_le_bytes());
+        bytes.push(format.id());
+        bytes.push(0);
+        bytes.extend_from_slice(&self.sample_index.to_le_bytes());
+        bytes.extend_from_slice(&self.position_ms.to_le_bytes());
+
+        match format {
+            FeedFormat::F32 => {
+                for sample in &self.samples {
+                    bytes.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            FeedFormat::I16 => {
+                for sample in &self.samples {
+                    let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+}
+
+struct Subscriber {
+    channel: Channel<InvokeResponseBody>,
+    format: FeedFormat,
+}
+
+/// Webview subscribers of the captured audio, sent as raw bytes over IPC
+/// channels instead of JSON events.
+#[derive(Default)]
+pub struct AudioFeed {
+    subscribers: Mutex<HashMap<u32, Subscriber>>,
+    next_id: AtomicU32,
+}
+
+impl AudioFeed {
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// Sends a chunk to every subscriber, encoding it once per format in use.
+    /// Subscribers whose channel fails, e.g. after a webview reload, are
+    /// dropped.
+    pub fn publish(&self, chunk: &AudioChunk) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut encoded: HashMap<FeedFormat, Vec<u8>> = HashMap::new();
+
+        subscribers.retain(|id, subscriber| {
+            let bytes = encoded
+                .entry(subscriber.format)
+                .or_insert_with(|| chunk.encode(subscriber.format))
+                .clone();
+            match subscriber.channel.send(InvokeResponseBody::Raw(bytes)) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Dropping audio feed subscriber {}: {}", id, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+/// Starts sending the captured audio to `channel`. Returns the id to pass to
+/// `unsubscribe_audio_feed`. While any subscriber exists, the JSON
+/// `audio_chunk` event is no longer emitted.
+#[tauri::command]
+pub fn subscribe_audio_feed(
+    feed: State<'_, AudioFeed>,
+    channel: Channel<InvokeResponseBody>,
+    format: Option<FeedFormat>,
+) -> u32 {
+    let id = feed.next_id.fetch_add(1, Ordering::Relaxed);
+    let format = format.unwrap_or(FeedFormat::F32);
+    info!("Audio feed subscriber {} added ({:?})", id, format);
+
+    feed.subscribers
+        .lock()
+        .unwrap()
+        .insert(id, Subscriber { channel, format });
+    id
+}
+
+#[tauri::command]
+pub fn unsubscribe_audio_feed(feed: State<'_, AudioFeed>, id: u32) -> bool {
+    let removed = feed.subscribers.lock().unwrap().remove(&id).is_some();
+    if removed {
+        info!("Audio feed subscriber {} removed", id);
+    }
+    removed
+}
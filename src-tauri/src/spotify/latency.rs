@@ -0,0 +1,169 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Fallback for the latency of the device and OS mixer behind the rodio
+/// queue, used when cpal doesn't report the device's buffer. The per-device
+/// offset corrects either where it's off.
+pub const DEVICE_LATENCY_FALLBACK_MS: i64 = 25;
+
+#[derive(Debug, Clone, Default)]
+pub struct LatencyConfig {
+    /// Manual corrections by output device name, added to the estimated
+    /// latency. See `devices::OUTPUT_OFFSETS_KEY`.
+    pub device_offsets_ms: HashMap<String, i64>,
+}
+
+struct OpenDevice {
+    name: Option<String>,
+    /// Latency of the device's buffer, if cpal reports it.
+    buffer: Option<Duration>,
+}
+
+/// What the device part of the latency is worked out from.
+#[derive(Default)]
+struct DeviceLatency {
+    /// `None` without an output device.
+    open: Option<OpenDevice>,
+    offsets_ms: HashMap<String, i64>,
+}
+
+impl DeviceLatency {
+    fn latency_us(&self) -> i64 {
+        let Some(open) = &self.open else {
+            return 0;
+        };
+        let offset_ms = open
+            .name
+            .as_ref()
+            .and_then(|name| self.offsets_ms.get(name))
+            .copied()
+            .unwrap_or(0);
+        let buffer_us = open
+            .buffer
+            .map_or(DEVICE_LATENCY_FALLBACK_MS * 1000, |buffer| {
+                buffer.as_micros() as i64
+            });
+        buffer_us + offset_ms * 1000
+    }
+}
+
+/// How far the audible output trails the captured samples, kept up to date
+/// by the sink and read by the analysis feed and the player events.
+#[derive(Default)]
+pub struct OutputLatency {
+    queued_us: AtomicU64,
+    device_us: AtomicI64,
+    /// Only touched when the device or an offset changes, readers go by
+    /// `device_us`.
+    device: Mutex<DeviceLatency>,
+}
+
+impl OutputLatency {
+    /// Replaces the per-device offsets, e.g. with the configured ones.
+    pub fn set_offsets(&self, offsets_ms: HashMap<String, i64>) {
+        self.update_device(|device| device.offsets_ms = offsets_ms);
+    }
+
+    /// Changes the offset of one device, `None` removes it. Applies right
+    /// away if that device is open.
+    pub fn set_offset(&self, device_name: &str, offset_ms: Option<i64>) {
+        self.update_device(|device| match offset_ms {
+            Some(offset_ms) => {
+                device.offsets_ms.insert(device_name.to_string(), offset_ms);
+            }
+            None => {
+                device.offsets_ms.remove(device_name);
+            }
+        });
+    }
+
+    /// Sets the device part for a newly opened output device, from the
+    /// latency of its buffer if that is known.
+    pub fn set_device(&self, device_name: Option<&str>, buffer: Option<Duration>) {
+        self.update_device(|device| {
+            device.open = Some(OpenDevice {
+                name: device_name.map(str::to_string),
+                buffer,
+            })
+        });
+    }
+
+    /// For sinks without an output device.
+    pub fn clear_device(&self) {
+        self.update_device(|device| device.open = None);
+    }
+
+    fn update_device(&self, change: impl FnOnce(&mut DeviceLatency)) {
+        let mut device = self.device.lock().unwrap();
+        change(&mut device);
+        self.device_us.store(device.latency_us(), Ordering::Relaxed);
+    }
+
+    pub fn set_queued(&self, queued: Duration) {
+        self.queued_us
+            .store(queued.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Audio queued in the sink, without the device part.
+    pub fn queued(&self) -> Duration {
+        Duration::from_micros(self.queued_us.load(Ordering::Relaxed))
+    }
+
+    pub fn total(&self) -> Duration {
+        let total_us =
+            self.queued_us.load(Ordering::Relaxed) as i64 + self.device_us.load(Ordering::Relaxed);
+        Duration::from_micros(total_us.max(0) as u64)
+    }
+
+    /// The position that is audible now, for a position the player reports
+    /// for the samples it is writing.
+    pub fn audible_position_ms(&self, position_ms: u32) -> u32 {
+        position_ms.saturating_sub(self.total().as_millis() as u32)
+    }
+}
+
+/// Holds items back until the audio they belong to is audible.
+pub struct DelayLine<T> {
+    items: VecDeque<(Instant, T)>,
+}
+
+impl<T> Default for DelayLine<T> {
+    fn default() -> Self {
+        Self {
+            items: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> DelayLine<T> {
+    pub fn push(&mut self, item: T, delay: Duration) {
+        // Keep the order even if the latency shrank meanwhile
+        let due = self
+            .items
+            .back()
+            .map_or(Instant::now() + delay, |&(last, _)| {
+                last.max(Instant::now() + delay)
+            });
+        self.items.push_back((due, item));
+    }
+
+    /// Time until the next item is due, if any is waiting.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.items
+            .front()
+            .map(|(due, _)| due.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn pop_due(&mut self) -> Option<T> {
+        if self.items.front()?.0 > Instant::now() {
+            return None;
+        }
+        self.items.pop_front().map(|(_, item)| item)
+    }
+}
@@ -1,18 +1,104 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
 use futures_util::StreamExt;
-use tauri::{AppHandle, Emitter};
+use log::{debug, error, info};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
+pub mod analysis;
 mod captured_rodio_sink;
 mod config;
 mod core;
+pub mod devices;
 mod event_handler;
+pub mod feed;
+mod latency;
+mod null_sink;
+mod reframer;
+mod ring;
 mod setup;
+mod sink_buffer;
+pub mod stats;
+
+/// How often the setup loop checks that the capture pipeline is alive.
+const CAPTURE_HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Emitted with the error when Spotify couldn't start or stopped with one.
+pub const SPOTIFY_SETUP_FAILED_EVENT: &str = "spotify_setup_failed";
+
+/// Lets commands reach the running Spotify instance.
+#[derive(Default)]
+pub struct SpotifyHandle {
+    /// Set while `setup` runs, so only one instance is started.
+    running: AtomicBool,
+    restart_capture: Mutex<Option<UnboundedSender<()>>>,
+    switch_output: Mutex<Option<devices::OutputSwitch>>,
+    latency: Mutex<Option<Arc<latency::OutputLatency>>>,
+}
+
+/// Rebuilds the capture pipeline with the current settings, without
+/// restarting playback or the app.
+#[tauri::command]
+pub fn restart_audio_pipeline(spotify: State<'_, SpotifyHandle>) -> Result<(), String> {
+    match spotify.restart_capture.lock().unwrap().as_ref() {
+        Some(sender) => sender
+            .send(())
+            .map_err(|_| "Spotify is shutting down".to_string()),
+        None => Err("Spotify is not running".to_string()),
+    }
+}
+
+/// Runs `setup` in the background unless it is already running. Errors are
+/// logged and emitted, after which calling this again starts over.
+pub fn start(handle: AppHandle, display_name: String, app_dir: PathBuf) {
+    if handle
+        .state::<SpotifyHandle>()
+        .running
+        .swap(true, Ordering::AcqRel)
+    {
+        info!("Spotify is already running");
+        return;
+    }
+
+    info!("Starting Spotify setup as speaker: {}", display_name);
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = setup(Box::new(handle.clone()), &display_name, app_dir).await {
+            error!("Spotify setup failed: {}", e);
+            if let Err(emit_error) = handle.emit(SPOTIFY_SETUP_FAILED_EVENT, e.to_string()) {
+                error!(
+                    "Failed to emit {}: {}",
+                    SPOTIFY_SETUP_FAILED_EVENT, emit_error
+                );
+            }
+        }
+        handle
+            .state::<SpotifyHandle>()
+            .running
+            .store(false, Ordering::Release);
+    });
+}
 
 pub async fn setup(
     handle: Box<AppHandle>,
     display_name: &str,
+    app_dir: PathBuf,
 ) -> Result<(), Box<(dyn std::error::Error + Send + Sync)>> {
-    let config = config::SpotifyConfig::new(display_name);
-    let mut spotify = core::SpotifyCore::new(config, handle.clone()).await;
+    let config = config::SpotifyConfig::new(display_name, &app_dir);
+    let mut spotify = core::SpotifyCore::new(config, handle.clone()).await?;
+
+    let (restart_tx, mut restart_rx) = unbounded_channel();
+    let spotify_handle = handle.state::<SpotifyHandle>();
+    *spotify_handle.restart_capture.lock().unwrap() = Some(restart_tx);
+    *spotify_handle.switch_output.lock().unwrap() = Some(spotify.output_switch.clone());
+    *spotify_handle.latency.lock().unwrap() = Some(spotify.output_latency());
+    let mut capture_health = tokio::time::interval(CAPTURE_HEALTH_INTERVAL);
 
     loop {
         tokio::select! {
@@ -27,31 +113,43 @@ pub async fn setup(
                         spotify.handle_discovery_event(credentials).await;
                     },
                     None => {
-                        println!("[ERROR] Discovery stopped unexpectedly");
+                        error!("Discovery stopped unexpectedly");
                     }
                 }
             },
             _ = async {}, if spotify.connecting && spotify.last_credentials.is_some() => {
                 match spotify.attempt_connection().await {
                     Ok(auth_token) => {
-                        handle.emit("spotify_new_connection", auth_token)?;
+                        if let Err(e) = handle.emit("spotify_new_connection", auth_token) {
+                            error!("Failed to emit spotify_new_connection: {}", e);
+                        }
                     },
                     Err(_) => {
-                        println!("[ERROR] Spotify connection attempt failed");
+                        error!("Spotify connection attempt failed");
                         continue; // Skip rest of this branch and loop back in select!
                     }
                 }
             },
             _ = async {
                 if let Some(task) = spotify.spirc_task.as_mut() {
-                    println!("awaiting spirc_task");
+                    debug!("Awaiting spirc task");
                     task.await;
                 }
             }, if spotify.spirc_task.is_some() && !spotify.connecting => {
                 spotify.handle_spirc_completion().await;
             },
             _ = async {}, if spotify.player.is_invalid() => {
-                println!("[ERROR] Player shut down unexpectedly");
+                error!("Player shut down unexpectedly");
+            },
+            Some(()) = restart_rx.recv() => {
+                info!("Restarting the capture pipeline");
+                spotify.config.reload_capture();
+                if let Err(e) = spotify.restart_capture() {
+                    error!("Failed to restart capture pipeline: {}", e);
+                }
+            },
+            _ = capture_health.tick() => {
+                spotify.ensure_capture_running();
             },
             _ = tokio::signal::ctrl_c() => {
                 break;
@@ -60,13 +158,16 @@ pub async fn setup(
         };
     }
 
+    *spotify_handle.restart_capture.lock().unwrap() = None;
+    *spotify_handle.switch_output.lock().unwrap() = None;
+    *spotify_handle.latency.lock().unwrap() = None;
     spotify.shutdown().await;
 
     let mut shutdown_tasks = tokio::task::JoinSet::new();
 
     if let Some(spirc) = spotify.spirc {
         if let Err(e) = spirc.shutdown() {
-            println!("[ERROR] error sending spirc shutdown message: {}", e);
+            error!("Error sending spirc shutdown message: {}", e);
         }
 
         if let Some(spirc_task) = spotify.spirc_task {
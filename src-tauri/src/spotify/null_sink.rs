@@ -0,0 +1,143 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use librespot::playback::{
+    audio_backend::{Sink, SinkError, SinkResult},
+    convert::Converter,
+    decoder::AudioPacket,
+    NUM_CHANNELS, SAMPLE_RATE,
+};
+use log::debug;
+
+use super::analysis::clipping::ClipCounters;
+use super::devices::{null_sink_reason, OutputDeviceChanged, OutputSwitcher, SinkState};
+use super::latency::OutputLatency;
+use super::ring::SampleRing;
+use super::stats::PipelineCounters;
+
+/// Falling behind real time by more than this restarts the clock.
+const RESYNC_AFTER: Duration = Duration::from_millis(100);
+
+/// A sink without an output device, for machines without audio output and
+/// for CI. It takes the audio at the rate a device would and still feeds the
+/// capture pipeline.
+pub struct NullSink {
+    ring: Arc<SampleRing>,
+    clip_counters: Arc<ClipCounters>,
+    latency: Arc<OutputLatency>,
+    counters: Arc<PipelineCounters>,
+    switcher: OutputSwitcher,
+    /// Whether the sink mode asks for it, rather than no device opening.
+    configured: bool,
+    /// How far ahead of real time the player may write, like the queue of a
+    /// device sink.
+    max_ahead: Duration,
+    /// When the first frame written since `start` would have played.
+    clock: Option<Instant>,
+    frames: u64,
+}
+
+impl NullSink {
+    pub fn new(
+        ring: Arc<SampleRing>,
+        clip_counters: Arc<ClipCounters>,
+        latency: Arc<OutputLatency>,
+        counters: Arc<PipelineCounters>,
+        switcher: OutputSwitcher,
+        configured: bool,
+        max_ahead: Duration,
+    ) -> Self {
+        latency.clear_device();
+        // Later requests are refused by the command right away
+        switcher.set_state(SinkState::Null { configured });
+
+        Self {
+            ring,
+            clip_counters,
+            latency,
+            counters,
+            switcher,
+            configured,
+            max_ahead,
+            clock: None,
+            frames: 0,
+        }
+    }
+
+    /// Waits until the audio written so far is at most `max_ahead` ahead of
+    /// real time. Returns how far ahead it is.
+    fn pace(&mut self, frames: usize) -> Duration {
+        let now = Instant::now();
+        let clock = match self.clock {
+            // Fell behind, e.g. the player stalled. Start over instead of
+            // letting it catch up in a burst.
+            Some(clock) if clock + self.played() + RESYNC_AFTER < now => None,
+            clock => clock,
+        };
+        let clock = clock.unwrap_or_else(|| {
+            self.frames = 0;
+            now
+        });
+        self.clock = Some(clock);
+        self.frames += frames as u64;
+
+        let played_until = clock + self.played();
+        if let Some(wait) = played_until
+            .checked_sub(self.max_ahead)
+            .and_then(|wake| wake.checked_duration_since(now))
+        {
+            thread::sleep(wait);
+        }
+        played_until.saturating_duration_since(Instant::now())
+    }
+
+    /// Refuses requests sent before the sink was created. Device switches
+    /// need a device sink.
+    fn refuse_switch(&self) {
+        if let Some(requested) = self.switcher.take_request() {
+            self.switcher.report(OutputDeviceChanged {
+                requested,
+                device: None,
+                error: Some(null_sink_reason(self.configured)),
+            });
+        }
+    }
+
+    fn played(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / SAMPLE_RATE as f64)
+    }
+}
+
+impl Sink for NullSink {
+    fn start(&mut self) -> SinkResult<()> {
+        debug!("NullSink: Start called");
+        self.refuse_switch();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        debug!("NullSink: Stop called");
+        self.clock = None;
+        self.latency.set_queued(Duration::ZERO);
+        Ok(())
+    }
+
+    fn write(&mut self, packet: AudioPacket, _converter: &mut Converter) -> SinkResult<()> {
+        self.refuse_switch();
+
+        let samples = packet
+            .samples()
+            .map_err(|e| SinkError::OnWrite(format!("NullSink Samples Error: {}", e)))?;
+
+        self.clip_counters.record(samples);
+        self.ring.write(samples);
+        self.counters.produced(samples.len());
+
+        let ahead = self.pace(samples.len() / NUM_CHANNELS as usize);
+        self.latency.set_queued(ahead);
+        Ok(())
+    }
+}
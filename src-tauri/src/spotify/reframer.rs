@@ -0,0 +1,292 @@
+use std::{collections::VecDeque, time::Duration};
+
+use super::feed::{AudioChunk, FeedFormat};
+
+/// Frames buffered ahead of the output beyond this many hops are skipped, so
+/// the feed can't drift behind the audio after a burst.
+const MAX_LEAD_HOPS: f64 = 8.0;
+
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    /// Frames per chunk sent to the webview.
+    pub frame_size: usize,
+    /// Chunks per second. 0 sends back-to-back chunks, at about
+    /// `sample_rate / decimation / frame_size` per second. Higher rates make
+    /// consecutive chunks overlap, lower ones leave gaps. The default of 60
+    /// overlaps slightly with the default frame size, so there are no gaps.
+    pub rate_hz: f32,
+    /// Mixes the channels down to mono.
+    pub downmix: bool,
+    /// Keeps every n-th frame, averaging the ones in between.
+    pub decimation: usize,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            frame_size: 1024,
+            rate_hz: 60.0,
+            downmix: false,
+            decimation: 1,
+        }
+    }
+}
+
+/// Turns the captured chunks, which arrive in bursts of whatever size the
+/// player writes, into fixed-size chunks at a steady rate.
+pub struct Reframer {
+    frame_size: usize,
+    decimation: usize,
+    in_channels: usize,
+    out_channels: usize,
+    out_rate: f64,
+    /// Output frames per tick.
+    hop: f64,
+
+    /// Output samples, interleaved.
+    buffer: VecDeque<f32>,
+    /// Feed index of the first frame in `buffer`.
+    buffer_index: u64,
+    /// Track positions by feed index, one per pushed chunk.
+    anchors: VecDeque<(u64, f64)>,
+    /// Where in `buffer`, in frames, the next chunk ends.
+    next_end: f64,
+    /// Input frames summed towards the next decimated frame.
+    accumulator: Vec<f32>,
+    accumulated: usize,
+    /// Chunks skipped to catch up since the last `take_skipped`.
+    skipped: u64,
+}
+
+impl Reframer {
+    pub fn new(config: FeedConfig, sample_rate: u32, channels: usize) -> Self {
+        let in_channels = channels.max(1);
+        let out_channels = if config.downmix { 1 } else { in_channels };
+        let frame_size = config.frame_size.max(1);
+        let decimation = config.decimation.max(1);
+        let out_rate = sample_rate as f64 / decimation as f64;
+        let hop = if config.rate_hz > 0.0 {
+            out_rate / config.rate_hz as f64
+        } else {
+            frame_size as f64
+        };
+
+        Self {
+            frame_size,
+            decimation,
+            in_channels,
+            out_channels,
+            out_rate,
+            hop,
+            buffer: VecDeque::with_capacity(frame_size * out_channels * 4),
+            buffer_index: 0,
+            anchors: VecDeque::new(),
+            next_end: frame_size as f64,
+            accumulator: vec![0.0; out_channels],
+            accumulated: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Time between chunks, the caller calls `tick` at this interval.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.hop / self.out_rate)
+    }
+
+    /// Adds captured samples whose first frame plays at `position_ms`.
+    pub fn push(&mut self, samples: &[f32], position_ms: f64) {
+        // Frames still in the decimation accumulator belong to the position
+        // before, which is close enough
+        let index = self.buffer_index + (self.buffer.len() / self.out_channels) as u64;
+        self.anchors.push_back((index, position_ms));
+
+        for frame in samples.chunks_exact(self.in_channels) {
+            if self.out_channels == 1 {
+                self.accumulator[0] += frame.iter().sum::<f32>() / self.in_channels as f32;
+            } else {
+                for (sum, sample) in self.accumulator.iter_mut().zip(frame) {
+                    *sum += sample;
+                }
+            }
+
+            self.accumulated += 1;
+            if self.accumulated == self.decimation {
+                for sum in self.accumulator.iter_mut() {
+                    self.buffer.push_back(*sum / self.decimation as f32);
+                    *sum = 0.0;
+                }
+                self.accumulated = 0;
+            }
+        }
+    }
+
+    /// The next chunk, or `None` if not enough audio has arrived for it.
+    pub fn tick(&mut self) -> Option<AudioChunk> {
+        let buffered = (self.buffer.len() / self.out_channels) as f64;
+        if buffered - self.next_end > self.hop * MAX_LEAD_HOPS {
+            self.skipped += ((buffered - self.next_end) / self.hop) as u64;
+            self.next_end = buffered;
+        }
+        if buffered < self.next_end {
+            return None;
+        }
+
+        let end = self.next_end.floor() as usize;
+        let start = end - self.frame_size;
+        let sample_index = self.buffer_index + start as u64;
+        let chunk = AudioChunk {
+            sample_rate: self.out_rate.round() as u32,
+            channels: self.out_channels,
+            format: FeedFormat::F32,
+            sample_index,
+            position_ms: self.position_at(sample_index),
+            samples: self
+                .buffer
+                .range(start * self.out_channels..end * self.out_channels)
+                .copied()
+                .collect(),
+        };
+
+        // Keep what the following chunks still need
+        self.next_end += self.hop;
+        let keep_from = (self.next_end.floor() as usize).saturating_sub(self.frame_size);
+        let drop = keep_from.min(end);
+        self.buffer.drain(..drop * self.out_channels);
+        self.buffer_index += drop as u64;
+        self.next_end -= drop as f64;
+
+        // The anchor covering the start of the buffer is the oldest needed
+        while self
+            .anchors
+            .get(1)
+            .is_some_and(|&(index, _)| index <= self.buffer_index)
+        {
+            self.anchors.pop_front();
+        }
+
+        Some(chunk)
+    }
+
+    /// Chunks skipped to keep up with the audio since the last call.
+    pub fn take_skipped(&mut self) -> u64 {
+        std::mem::take(&mut self.skipped)
+    }
+
+    fn position_at(&self, index: u64) -> f64 {
+        match self
+            .anchors
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= index)
+        {
+            Some(&(start, position_ms)) => {
+                position_ms + (index - start) as f64 * 1000.0 / self.out_rate
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn mono(frame_size: usize, rate_hz: f32) -> Reframer {
+        let config = FeedConfig {
+            frame_size,
+            rate_hz,
+            ..Default::default()
+        };
+        Reframer::new(config, SAMPLE_RATE, 1)
+    }
+
+    /// Mono samples whose values are their feed indices.
+    fn ramp(from: usize, frames: usize) -> Vec<f32> {
+        (from..from + frames).map(|i| i as f32).collect()
+    }
+
+    #[test]
+    fn interval_follows_the_rate() {
+        assert_eq!(mono(1024, 60.0).hop, 800.0);
+        assert_eq!(
+            mono(1024, 60.0).interval(),
+            Duration::from_secs_f64(1.0 / 60.0)
+        );
+
+        // Back-to-back chunks
+        let reframer = mono(1024, 0.0);
+        assert_eq!(reframer.hop, 1024.0);
+        assert_eq!(
+            reframer.interval(),
+            Duration::from_secs_f64(1024.0 / 48000.0)
+        );
+
+        // The hop is in decimated frames, the interval stays the same
+        let config = FeedConfig {
+            decimation: 2,
+            ..Default::default()
+        };
+        let reframer = Reframer::new(config, SAMPLE_RATE, 2);
+        assert_eq!(reframer.hop, 400.0);
+        assert_eq!(reframer.interval(), Duration::from_secs_f64(1.0 / 60.0));
+    }
+
+    #[test]
+    fn chunks_overlap_when_the_rate_exceeds_the_frame_size() {
+        // 60 chunks per second of 1024 frames is 800 frames apart
+        let mut reframer = mono(1024, 60.0);
+        reframer.push(&ramp(0, 4000), 0.0);
+
+        let first = reframer.tick().unwrap();
+        let second = reframer.tick().unwrap();
+        assert_eq!(first.sample_index, 0);
+        assert_eq!(second.sample_index, 800);
+        assert_eq!(first.samples, ramp(0, 1024));
+        assert_eq!(second.samples, ramp(800, 1024));
+        assert_eq!(second.position_ms, 800.0 * 1000.0 / 48000.0);
+        assert_eq!(reframer.take_skipped(), 0);
+    }
+
+    #[test]
+    fn chunks_leave_gaps_when_the_rate_is_low() {
+        // 10 chunks per second of 1024 frames is 4800 frames apart
+        let mut reframer = mono(1024, 10.0);
+        reframer.push(&ramp(0, 6000), 0.0);
+
+        assert_eq!(reframer.tick().unwrap().samples, ramp(0, 1024));
+        let second = reframer.tick().unwrap();
+        assert_eq!(second.sample_index, 4800);
+        assert_eq!(second.samples, ramp(4800, 1024));
+    }
+
+    #[test]
+    fn waits_for_a_whole_chunk() {
+        let mut reframer = mono(1024, 60.0);
+        reframer.push(&ramp(0, 1000), 0.0);
+        assert!(reframer.tick().is_none());
+        reframer.push(&ramp(1000, 24), 1000.0 * 1000.0 / 48000.0);
+        assert_eq!(reframer.tick().unwrap().samples, ramp(0, 1024));
+    }
+
+    #[test]
+    fn skips_ahead_after_a_stall() {
+        let mut reframer = mono(1024, 60.0);
+        // A second of audio with no ticks, far more than MAX_LEAD_HOPS
+        reframer.push(&ramp(0, 48000), 0.0);
+
+        let chunk = reframer.tick().unwrap();
+        assert_eq!(chunk.sample_index, 48000 - 1024);
+        assert_eq!(chunk.samples, ramp(48000 - 1024, 1024));
+        assert_eq!(chunk.position_ms, (48000.0 - 1024.0) * 1000.0 / 48000.0);
+        assert_eq!(reframer.take_skipped(), (48000 - 1024) / 800);
+        assert_eq!(reframer.take_skipped(), 0);
+
+        // Back to the normal pace from the newest audio
+        assert!(reframer.tick().is_none());
+        reframer.push(&ramp(48000, 800), 1000.0);
+        assert_eq!(reframer.tick().unwrap().sample_index, 48000 - 1024 + 800);
+        assert_eq!(reframer.take_skipped(), 0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::{
+    atomic::{fence, AtomicU32, AtomicU64, Ordering},
+    Arc,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+/// Captured samples shared between the sink and the capture thread. There is
+/// one writer, the sink, which never blocks or allocates. Readers that fall
+/// behind by more than the capacity lose the oldest samples.
+pub struct SampleRing {
+    /// `f32` bits, so the storage can be shared without locks.
+    slots: Box<[AtomicU32]>,
+    mask: u64,
+    channels: u64,
+    /// Samples fully written since the ring was created.
+    written: AtomicU64,
+    /// End of the write in progress. Slots before `claimed - capacity` may
+    /// already hold newer samples.
+    claimed: AtomicU64,
+    ready_tx: Sender<()>,
+    ready_rx: Receiver<()>,
+}
+
+impl SampleRing {
+    /// A ring holding at least `frames` frames of `channels` samples each.
+    pub fn new(frames: usize, channels: usize) -> Self {
+        let capacity = (frames * channels.max(1)).next_power_of_two();
+        let (ready_tx, ready_rx) = bounded(1);
+
+        Self {
+            slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            mask: capacity as u64 - 1,
+            channels: channels.max(1) as u64,
+            written: AtomicU64::new(0),
+            claimed: AtomicU64::new(0),
+            ready_tx,
+            ready_rx,
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.mask + 1
+    }
+
+    /// The first frame start at or after `position`.
+    fn frame_start(&self, position: u64) -> u64 {
+        position.div_ceil(self.channels) * self.channels
+    }
+
+    /// Appends interleaved samples. Only one thread may write at a time.
+    pub fn write(&self, samples: &[f64]) {
+        let start = self.written.load(Ordering::Relaxed);
+        let end = start + samples.len() as u64;
+
+        self.claimed.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // Only the newest samples fit if the write is larger than the ring
+        let skip = samples.len().saturating_sub(self.slots.len());
+        for (position, &sample) in (start + skip as u64..).zip(&samples[skip..]) {
+            self.slots[(position & self.mask) as usize]
+                .store((sample as f32).to_bits(), Ordering::Relaxed);
+        }
+
+        self.written.store(end, Ordering::Release);
+        // Wakes a waiting reader, it's fine if one is already pending
+        let _ = self.ready_tx.try_send(());
+    }
+
+    /// Ready to receive after a write, for use in `select!`.
+    pub fn ready(&self) -> &Receiver<()> {
+        &self.ready_rx
+    }
+}
+
+/// A reader's position in a `SampleRing`.
+pub struct RingReader {
+    ring: Arc<SampleRing>,
+    next: u64,
+}
+
+impl RingReader {
+    /// Starts reading at the samples written after this call.
+    pub fn new(ring: Arc<SampleRing>) -> Self {
+        let next = ring.written.load(Ordering::Acquire);
+        Self { ring, next }
+    }
+
+    /// Appends the samples written since the last read to `out`. If the writer
+    /// got more than the capacity ahead, the reader skips to the newest
+    /// window. Returns how many samples were skipped.
+    pub fn read(&mut self, out: &mut Vec<f32>) -> u64 {
+        let ring = &*self.ring;
+        let end = ring.written.load(Ordering::Acquire);
+        let mut start = self
+            .next
+            .max(ring.frame_start(end.saturating_sub(ring.capacity())));
+
+        let copied_from = out.len();
+        out.extend((start..end).map(|position| {
+            f32::from_bits(ring.slots[(position & ring.mask) as usize].load(Ordering::Relaxed))
+        }));
+
+        // Drop what a concurrent write overwrote while copying, keeping whole
+        // frames
+        fence(Ordering::Acquire);
+        let overwritten = ring
+            .claimed
+            .load(Ordering::Relaxed)
+            .saturating_sub(ring.capacity());
+        if overwritten > start {
+            let discard = (ring.frame_start(overwritten).min(end) - start) as usize;
+            out.drain(copied_from..copied_from + discard);
+            start += discard as u64;
+        }
+
+        let skipped = start - self.next;
+        self.next = end;
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Interleaved stereo samples whose values are their positions in the
+    /// stream, exact in `f32` below 2^24.
+    fn samples(start: u64, len: usize) -> Vec<f64> {
+        (start..start + len as u64).map(|i| i as f64).collect()
+    }
+
+    #[test]
+    fn reads_across_the_wraparound() {
+        let ring = Arc::new(SampleRing::new(8, 2));
+        let mut reader = RingReader::new(ring.clone());
+        let mut out = Vec::new();
+
+        let mut written = 0;
+        for len in [6, 10, 12, 4, 14, 8] {
+            ring.write(&samples(written, len));
+            written += len as u64;
+            assert_eq!(reader.read(&mut out), 0);
+        }
+
+        let expected: Vec<f32> = (0..written).map(|i| i as f32).collect();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn overrun_skips_to_the_newest_whole_frames() {
+        let ring = Arc::new(SampleRing::new(8, 2));
+        let mut reader = RingReader::new(ring.clone());
+        let mut out = Vec::new();
+
+        // 40 samples into a ring of 16, the reader missed the first 24
+        ring.write(&samples(0, 14));
+        ring.write(&samples(14, 26));
+        assert_eq!(reader.read(&mut out), 24);
+        assert_eq!(out, (24..40).map(|i| i as f32).collect::<Vec<_>>());
+
+        // Reading continues right after the skipped part
+        out.clear();
+        ring.write(&samples(40, 4));
+        assert_eq!(reader.read(&mut out), 0);
+        assert_eq!(out, vec![40.0, 41.0, 42.0, 43.0]);
+    }
+
+    #[test]
+    fn write_larger_than_the_ring_keeps_the_newest_samples() {
+        let ring = Arc::new(SampleRing::new(4, 2));
+        let mut reader = RingReader::new(ring.clone());
+        let mut out = Vec::new();
+
+        ring.write(&samples(0, 20));
+        assert_eq!(reader.read(&mut out), 12);
+        assert_eq!(out, (12..20).map(|i| i as f32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn concurrent_reads_never_return_torn_samples() {
+        const TOTAL: u64 = 1 << 22;
+        let ring = Arc::new(SampleRing::new(16, 2));
+        let mut reader = RingReader::new(ring.clone());
+
+        let writer = {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                let mut written = 0;
+                let mut len = 2;
+                while written < TOTAL {
+                    ring.write(&samples(written, len));
+                    written += len as u64;
+                    // Whole frames of varying size, some larger than the ring
+                    len = len % 40 + 2;
+                }
+                written
+            })
+        };
+
+        let mut out = Vec::new();
+        let mut next = 0;
+        let mut skipped = 0;
+        while next < TOTAL {
+            out.clear();
+            let skip = reader.read(&mut out);
+            skipped += skip;
+            next += skip;
+            assert_eq!(next % 2, 0, "read started mid-frame");
+            for &sample in &out {
+                assert_eq!(sample, next as f32, "torn or out of order sample");
+                next += 1;
+            }
+        }
+
+        let written = writer.join().unwrap();
+        out.clear();
+        next += reader.read(&mut out) + out.len() as u64;
+        assert_eq!(next, written);
+        assert!(skipped < written);
+    }
+}
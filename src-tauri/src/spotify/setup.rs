@@ -1,88 +1,357 @@
 // Example in your Tauri main.rs or setup function
 
-use crossbeam_channel::{bounded, Receiver, Sender};
-use librespot::playback::{audio_backend::Sink, config::AudioFormat};
-use log::{debug, error, info};
-use once_cell::sync::OnceCell;
-use std::{sync::Mutex, thread};
-use tauri::{AppHandle, Emitter};
+use crossbeam_channel::{after, bounded, never, select, tick, unbounded, Receiver, Sender};
+use librespot::playback::{
+    audio_backend::Sink, config::AudioFormat, mixer::VolumeGetter, NUM_CHANNELS, SAMPLE_RATE,
+};
+use log::{debug, error, info, warn};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+use tauri::{AppHandle, Emitter, Manager};
 
-use crate::spotify::captured_rodio_sink::CaptureRodioSink;
+use crate::spotify::{
+    analysis::{
+        clipping::{ClipCounters, ClipTotals},
+        pipeline::EventSink,
+        Analysis, AnalysisConfig, AnalysisControl,
+    },
+    captured_rodio_sink::{CaptureRodioSink, SinkConfig, SinkMode},
+    devices::OutputSwitcher,
+    feed::AudioFeed,
+    latency::{DelayLine, OutputLatency},
+    null_sink::NullSink,
+    reframer::{FeedConfig, Reframer},
+    ring::{RingReader, SampleRing},
+    sink_buffer::{SamplePool, SinkBuffer},
+    stats::{
+        EmitTimer, PipelineCounters, PipelineStats, PipelineStatsStore, AUDIO_PIPELINE_STATS_EVENT,
+        REPORT_INTERVAL,
+    },
+};
 
 type CapturedAudioSample = f32;
 
-static CAPTURE_SENDER: OnceCell<Mutex<Sender<Vec<CapturedAudioSample>>>> = OnceCell::new();
-
-pub fn init_capture_channel(app_handle: Box<AppHandle>) -> Result<(), String> {
-    let (capture_tx, capture_rx): (
-        Sender<Vec<CapturedAudioSample>>,
-        Receiver<Vec<CapturedAudioSample>>,
-    ) = bounded(100);
-
-    // Spawn the emitter thread (consumes capture_rx)
-    let emitter_handle = app_handle.clone();
-    thread::spawn(move || {
-        println!("Capture emitter thread started.");
-        while let Ok(audio_chunk) = capture_rx.recv() {
-            if let Err(e) = emitter_handle.emit("audio_chunk", audio_chunk) {
-                eprintln!("Failed to emit audio_chunk: {}", e);
-                // break; // Optional: stop if emit fails
-            }
+/// Captured audio the ring holds for a capture thread that fell behind.
+const CAPTURE_RING_FRAMES: usize = 2 * SAMPLE_RATE as usize;
+
+/// The parts of the capture pipeline the player side needs to hold on to.
+/// They outlive restarts of the capture thread.
+#[derive(Clone)]
+pub struct CaptureChannel {
+    /// Written by the sink, read by the capture thread.
+    pub ring: Arc<SampleRing>,
+    /// Forwards player state changes to the analysis thread.
+    pub control: Sender<AnalysisControl>,
+    control_rx: Receiver<AnalysisControl>,
+    /// Updated by the sink, reported by the analysis thread.
+    pub clip_counters: Arc<ClipCounters>,
+    /// Updated by the sink, used to line the feed and the position events
+    /// up with the audible output.
+    pub latency: Arc<OutputLatency>,
+    /// Updated by the sink and the capture thread, reported by the latter.
+    pub counters: Arc<PipelineCounters>,
+}
+
+impl Default for CaptureChannel {
+    fn default() -> Self {
+        let (control, control_rx) = unbounded::<AnalysisControl>();
+
+        Self {
+            ring: Arc::new(SampleRing::new(CAPTURE_RING_FRAMES, NUM_CHANNELS as usize)),
+            control,
+            control_rx,
+            clip_counters: Arc::new(ClipCounters::default()),
+            latency: Arc::new(OutputLatency::default()),
+            counters: Arc::new(PipelineCounters::default()),
         }
-        println!("Capture emitter thread finished.");
-    });
+    }
+}
 
-    // Try to set the value in the OnceCell
-    CAPTURE_SENDER
-        .set(Mutex::new(capture_tx))
-        .map_err(|_| "Capture channel sender already initialized".to_string())?;
+/// What the emitter thread holds back until it is audible. Player state
+/// changes are delayed with the samples so they stay in order, and so are
+/// the clip counters read with each chunk.
+enum Delayed {
+    Chunk(Vec<CapturedAudioSample>, ClipTotals),
+    Control(AnalysisControl),
+}
 
-    println!("Capture channel initialized and sender stored globally.");
-    Ok(())
+/// Player state the capture thread has seen, replayed into the next one so
+/// a restart keeps the track and position.
+#[derive(Default)]
+struct ResumeState {
+    track: Option<AnalysisControl>,
+    paused: bool,
 }
 
-pub fn mk_capture_rodio_for_fn_ptr(device: Option<String>, format: AudioFormat) -> Box<dyn Sink> {
+impl ResumeState {
+    fn update(&mut self, control: &AnalysisControl) {
+        match control {
+            AnalysisControl::TrackChanged { .. } => self.track = Some(control.clone()),
+            AnalysisControl::Playing { .. } => self.paused = false,
+            AnalysisControl::Paused | AnalysisControl::Stopped => self.paused = true,
+            AnalysisControl::Seeked { .. } => {}
+        }
+    }
+
+    fn into_controls(self, position_ms: u32) -> Vec<AnalysisControl> {
+        let Some(track) = self.track else {
+            return Vec::new();
+        };
+
+        let mut controls = vec![track, AnalysisControl::Seeked { position_ms }];
+        controls.push(if self.paused {
+            AnalysisControl::Paused
+        } else {
+            AnalysisControl::Playing { position_ms }
+        });
+        controls
+    }
+}
+
+/// The capture thread, which runs the analysis and the visualizer feed. It
+/// is stopped when this is dropped.
+pub struct CapturePipeline {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<Vec<AnalysisControl>>>,
+}
+
+impl CapturePipeline {
+    /// Starts reading the audio written to `channel` from now on. `resume`
+    /// is the player state returned by the `stop` of the previous pipeline.
+    pub fn start(
+        channel: &CaptureChannel,
+        app_handle: AppHandle,
+        analysis_config: AnalysisConfig,
+        feed_config: FeedConfig,
+        resume: Vec<AnalysisControl>,
+    ) -> Result<Self, String> {
+        let (stop, stop_rx) = bounded::<()>(1);
+        let mut reader = RingReader::new(channel.ring.clone());
+        let ring_ready = channel.ring.ready().clone();
+        let control_rx = channel.control_rx.clone();
+        let clip_counters = channel.clip_counters.clone();
+        let latency = channel.latency.clone();
+        let counters = channel.counters.clone();
+
+        let thread = thread::Builder::new()
+            .name("capture-emitter".into())
+            .spawn(move || {
+                info!("Capture emitter thread started");
+                let mut analysis = Analysis::new(
+                    analysis_config,
+                    &clip_counters,
+                    EventSink::new(app_handle.clone()),
+                );
+                let mut state = ResumeState::default();
+                for control in resume {
+                    state.update(&control);
+                    analysis.handle_control(control);
+                }
+
+                let mut reframer = Reframer::new(feed_config, SAMPLE_RATE, NUM_CHANNELS as usize);
+                let ticker = tick(reframer.interval());
+                let reports = tick(REPORT_INTERVAL);
+                let mut emits = EmitTimer::default();
+                let mut delay = DelayLine::default();
+                loop {
+                    let due = delay.next_due().map_or_else(never, after);
+                    select! {
+                        recv(ring_ready) -> _ => {
+                            let mut audio_chunk = Vec::new();
+                            let skipped = reader.read(&mut audio_chunk);
+                            if skipped > 0 {
+                                warn!("Capture thread fell behind, skipped {} samples", skipped);
+                                counters.samples_dropped(skipped);
+                            }
+                            if !audio_chunk.is_empty() {
+                                let clips = clip_counters.snapshot();
+                                delay.push(Delayed::Chunk(audio_chunk, clips), latency.total());
+                            }
+                        },
+                        recv(due) -> _ => {},
+                        recv(ticker) -> _ => {
+                            let chunk = reframer.tick();
+                            counters.chunks_dropped(reframer.take_skipped());
+                            let Some(chunk) = chunk else { continue };
+
+                            // The JSON event is only a fallback for webviews that
+                            // didn't subscribe to the binary feed
+                            let started = Instant::now();
+                            let feed = app_handle.state::<AudioFeed>();
+                            if feed.has_subscribers() {
+                                feed.publish(&chunk);
+                                counters.emitted();
+                            } else if let Err(e) = app_handle.emit("audio_chunk", chunk) {
+                                error!("Failed to emit audio_chunk: {}", e);
+                                counters.chunks_dropped(1);
+                            } else {
+                                counters.emitted();
+                            }
+                            emits.record(started);
+                        },
+                        recv(reports) -> _ => {
+                            let stats =
+                                PipelineStats::collect(&counters, &mut emits, latency.queued());
+                            let store = app_handle.state::<PipelineStatsStore>();
+                            *store.latest.lock().unwrap() = stats.clone();
+                            if let Err(e) = app_handle.emit(AUDIO_PIPELINE_STATS_EVENT, stats) {
+                                error!("Failed to emit {}: {}", AUDIO_PIPELINE_STATS_EVENT, e);
+                            }
+                        },
+                        recv(control_rx) -> control => {
+                            // The player side is gone
+                            let Ok(control) = control else { break };
+                            delay.push(Delayed::Control(control), latency.total());
+                        },
+                        recv(stop_rx) -> _ => break,
+                    }
+
+                    while let Some(item) = delay.pop_due() {
+                        match item {
+                            Delayed::Chunk(mut audio_chunk, clips) => {
+                                let position_ms = analysis.position_ms();
+                                analysis.process(&mut audio_chunk, clips);
+                                reframer.push(&audio_chunk, position_ms);
+                            }
+                            Delayed::Control(control) => {
+                                state.update(&control);
+                                analysis.handle_control(control);
+                            }
+                        }
+                    }
+                }
+                let resume = state.into_controls(analysis.position_ms() as u32);
+                // Waits for the analyzers to save what they collected
+                drop(analysis);
+                info!("Capture emitter thread finished");
+                resume
+            })
+            .map_err(|e| format!("Failed to start capture thread: {}", e))?;
+
+        info!("Capture pipeline started");
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// False once the thread stopped or panicked.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stops the thread and waits for it. Returns the player state to resume
+    /// from, which is empty if the thread panicked or was already stopped.
+    pub fn stop(&mut self) -> Vec<AnalysisControl> {
+        let Some(thread) = self.thread.take() else {
+            return Vec::new();
+        };
+
+        let _ = self.stop.try_send(());
+        match thread.join() {
+            Ok(resume) => resume,
+            Err(_) => {
+                error!("Capture thread panicked");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl Drop for CapturePipeline {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Creates the capturing sink. `volume` is only given for the pre-volume
+/// capture tap, in which case the sink applies the software volume itself.
+/// Falls back to the null sink if the output device can't be opened.
+pub fn mk_capture_rodio(
+    device: Option<String>,
+    format: AudioFormat,
+    volume: Option<Box<dyn VolumeGetter + Send>>,
+    capture: &CaptureChannel,
+    sink_config: &SinkConfig,
+    switcher: OutputSwitcher,
+) -> Box<dyn Sink> {
     info!(
-        "mk_capture_rodio_for_fn_ptr called with format {:?} for device {:?}",
+        "mk_capture_rodio called with format {:?} for device {:?}",
         format, device
     );
 
-    let sender_mutex = CAPTURE_SENDER
-        .get()
-        .expect("FATAL: Capture channel sender was not initialized before creating sink.");
-    let capture_sender = sender_mutex
-        .lock()
-        .expect("FATAL: Failed to lock capture sender mutex.")
-        .clone();
-
-    let host = cpal::default_host();
+    if sink_config.mode == SinkMode::Null {
+        info!("Using the null sink, audio is not played");
+        return mk_null_sink(capture, sink_config, switcher, true);
+    }
 
-    // Check format support
-    if format != AudioFormat::S16 && format != AudioFormat::F32 {
-        panic!(
-            "CaptureRodioSink (via fn ptr) currently only supports F32 and S16 formats, got {:?}",
+    // `SpotifyCore::new` refuses other formats, the null sink takes any
+    if !CaptureRodioSink::supports(format) {
+        error!(
+            "CaptureRodioSink only supports F32 and S16, got {:?}, falling back to the null sink",
             format
         );
+        return mk_null_sink(capture, sink_config, switcher, false);
     }
 
-    let (rodio_sink, stream) = super::captured_rodio_sink::create_sink(&host, device)
-        .map_err(|e| {
-            error!(
-                "Failed to create underlying sink in mk_capture_rodio_for_fn_ptr: {}",
-                e
-            );
-            e
-        })
-        .expect("FATAL: Failed to create underlying rodio sink/stream.");
+    let host = cpal::default_host();
+    let output = match super::captured_rodio_sink::create_sink(&host, device) {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("{}, falling back to the null sink", e);
+            return mk_null_sink(capture, sink_config, switcher, false);
+        }
+    };
 
-    debug!("CaptureRodioSink (via fn ptr) underlying components created");
+    debug!("CaptureRodioSink underlying components created");
+    capture
+        .latency
+        .set_device(output.name.as_deref(), output.buffer_latency);
 
     let capture_sink = CaptureRodioSink {
-        rodio_sink,
+        rodio_sink: output.sink,
         format,
-        capture_sender, // Use the cloned sender
-        _stream: stream,
+        ring: capture.ring.clone(),
+        volume,
+        scaled: Vec::new(),
+        clip_counters: capture.clip_counters.clone(),
+        latency: capture.latency.clone(),
+        counters: capture.counters.clone(),
+        buffer: Arc::new(SinkBuffer::for_current_thread()),
+        max_queued_frames: sink_config.buffer_frames(),
+        f32_pool: SamplePool::default(),
+        s16_pool: SamplePool::default(),
+        device_name: output.name,
+        switcher,
+        draining: VecDeque::new(),
+        playing: false,
+        _stream: output.stream,
     };
 
     Box::new(capture_sink) // Return the boxed sink
 }
+
+/// `configured` if the sink mode asks for it, rather than it being a
+/// fallback.
+fn mk_null_sink(
+    capture: &CaptureChannel,
+    sink_config: &SinkConfig,
+    switcher: OutputSwitcher,
+    configured: bool,
+) -> Box<dyn Sink> {
+    Box::new(NullSink::new(
+        capture.ring.clone(),
+        capture.clip_counters.clone(),
+        capture.latency.clone(),
+        capture.counters.clone(),
+        switcher,
+        configured,
+        sink_config.buffer(),
+    ))
+}
@@ -0,0 +1,308 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::warn;
+use rodio::{Sample, Source};
+
+/// How long the player thread waits for the output before warning that it
+/// seems stuck, and again every this long until it gives up.
+const STALL_WARNING: Duration = Duration::from_secs(2);
+
+/// Played sample buffers kept for reuse, more than the sink ever queues.
+const POOLED_BUFFERS: usize = 64;
+
+/// Frames appended to the rodio sink that rodio hasn't played yet. The
+/// player thread waits on it for room instead of polling the sink. The
+/// output thread only updates an atomic and wakes the player thread, it never
+/// takes a lock.
+pub struct SinkBuffer {
+    queued_frames: AtomicUsize,
+    /// The thread that waits, woken whenever a buffer finished.
+    waiter: Thread,
+}
+
+impl SinkBuffer {
+    /// Only the calling thread may wait on it.
+    pub fn for_current_thread() -> Self {
+        Self {
+            queued_frames: AtomicUsize::new(0),
+            waiter: thread::current(),
+        }
+    }
+
+    pub fn queued_frames(&self) -> usize {
+        self.queued_frames.load(Ordering::Acquire)
+    }
+
+    /// Audio left to play at `sample_rate`.
+    pub fn queued(&self, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(self.queued_frames() as f64 / sample_rate as f64)
+    }
+
+    /// Wraps samples that are about to be appended, their frames count as
+    /// queued until rodio took them. The buffer goes back to `pool` once
+    /// rodio is done with it.
+    pub fn track<T: Sample>(
+        self: &Arc<Self>,
+        samples: Vec<T>,
+        pool: &SamplePool<T>,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Tracked<T> {
+        let frames = samples.len() / channels.max(1) as usize;
+        self.queued_frames.fetch_add(frames, Ordering::AcqRel);
+        Tracked {
+            samples,
+            position: 0,
+            channels,
+            sample_rate,
+            frames,
+            buffer: self.clone(),
+            pool: pool.returned.clone(),
+        }
+    }
+
+    fn played(&self, frames: usize) {
+        self.queued_frames.fetch_sub(frames, Ordering::AcqRel);
+    }
+
+    /// Gives back the frames of a buffer that wasn't played to the end and
+    /// wakes the waiting thread.
+    fn finished(&self, unplayed_frames: usize) {
+        self.played(unplayed_frames);
+        self.waiter.unpark();
+    }
+
+    /// Blocks until at most `max_frames` are queued. Returns false if the
+    /// output played nothing for `stall_timeout`, so a device that stopped
+    /// taking audio fails the write instead of hanging the player thread.
+    pub fn wait_for_room(&self, max_frames: usize, stall_timeout: Duration) -> bool {
+        debug_assert_eq!(thread::current().id(), self.waiter.id());
+
+        let mut last = self.queued_frames();
+        let mut progressed = Instant::now();
+        let mut warned = progressed;
+        loop {
+            let queued = self.queued_frames();
+            if queued <= max_frames {
+                return true;
+            }
+            let now = Instant::now();
+            if queued != last {
+                last = queued;
+                progressed = now;
+                warned = now;
+            } else if now - progressed >= stall_timeout {
+                return false;
+            } else if now - warned >= STALL_WARNING {
+                warn!(
+                    "Output hasn't played anything for {:?}, {} frames queued",
+                    now - progressed,
+                    queued
+                );
+                warned = now;
+            }
+            let wake = (progressed + stall_timeout).min(warned + STALL_WARNING);
+            thread::park_timeout(wake.saturating_duration_since(now));
+        }
+    }
+
+    /// Blocks until everything queued has played, for at most `timeout`.
+    /// Returns false if the output didn't drain in time.
+    pub fn wait_drained(&self, timeout: Duration) -> bool {
+        debug_assert_eq!(thread::current().id(), self.waiter.id());
+
+        let deadline = Instant::now() + timeout;
+        while self.queued_frames() > 0 {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            thread::park_timeout(left);
+        }
+        true
+    }
+}
+
+/// Sample buffers the output thread hands back once played, so the player
+/// thread refills them instead of allocating new ones.
+pub struct SamplePool<T> {
+    free: Receiver<Vec<T>>,
+    returned: Sender<Vec<T>>,
+}
+
+impl<T> Default for SamplePool<T> {
+    fn default() -> Self {
+        let (returned, free) = bounded(POOLED_BUFFERS);
+        Self { free, returned }
+    }
+}
+
+impl<T> SamplePool<T> {
+    /// An empty buffer with room for `len` samples, a played one if there is
+    /// one.
+    pub fn take(&self, len: usize) -> Vec<T> {
+        let mut buffer = self.free.try_recv().unwrap_or_default();
+        buffer.clear();
+        buffer.reserve(len);
+        buffer
+    }
+}
+
+/// Samples that count their frames off the `SinkBuffer` as rodio takes
+/// them. They give their buffer back to the pool when they're dropped, which
+/// rodio does once they're played or cleared.
+pub struct Tracked<T> {
+    samples: Vec<T>,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+    frames: usize,
+    buffer: Arc<SinkBuffer>,
+    pool: Sender<Vec<T>>,
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        // Dropped instead if the pool is full
+        let _ = self.pool.try_send(mem::take(&mut self.samples));
+        let played = self.position / self.channels.max(1) as usize;
+        self.buffer.finished(self.frames - played);
+    }
+}
+
+impl<T: Sample> Iterator for Tracked<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = *self.samples.get(self.position)?;
+        self.position += 1;
+        if self.position % self.channels.max(1) as usize == 0 {
+            self.buffer.played(1);
+        }
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.samples.len().saturating_sub(self.position);
+        (left, Some(left))
+    }
+}
+
+impl<T: Sample> Source for Tracked<T> {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len().saturating_sub(self.position))
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(
+            self.frames as f64 / self.sample_rate as f64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn stereo(buffer: &Arc<SinkBuffer>, pool: &SamplePool<f32>, frames: usize) -> Tracked<f32> {
+        let mut samples = pool.take(frames * 2);
+        samples.resize(frames * 2, 0.0);
+        buffer.track(samples, pool, 2, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn frames_count_off_as_they_play() {
+        let buffer = Arc::new(SinkBuffer::for_current_thread());
+        let pool = SamplePool::default();
+        let mut first = stereo(&buffer, &pool, 480);
+        let _second = stereo(&buffer, &pool, 480);
+        assert_eq!(buffer.queued_frames(), 960);
+        assert_eq!(buffer.queued(SAMPLE_RATE), Duration::from_millis(20));
+
+        // A frame only counts once all its channels are taken
+        first.next();
+        assert_eq!(buffer.queued_frames(), 960);
+        first.next();
+        assert_eq!(buffer.queued_frames(), 959);
+
+        first.by_ref().take(2 * 479).for_each(drop);
+        assert_eq!(buffer.queued_frames(), 480);
+        assert!(first.next().is_none());
+        drop(first);
+        assert_eq!(buffer.queued_frames(), 480);
+    }
+
+    #[test]
+    fn dropping_gives_back_the_unplayed_frames() {
+        let buffer = Arc::new(SinkBuffer::for_current_thread());
+        let pool = SamplePool::default();
+        let mut tracked = stereo(&buffer, &pool, 480);
+        tracked.by_ref().take(2 * 100 + 1).for_each(drop);
+        assert_eq!(buffer.queued_frames(), 380);
+
+        // Cleared from the sink, the half-taken frame counts as unplayed
+        drop(tracked);
+        assert_eq!(buffer.queued_frames(), 0);
+    }
+
+    #[test]
+    fn pool_reuses_played_buffers() {
+        let buffer = Arc::new(SinkBuffer::for_current_thread());
+        let pool = SamplePool::default();
+        let tracked = stereo(&buffer, &pool, 480);
+        let address = tracked.samples.as_ptr();
+        drop(tracked);
+
+        let reused = pool.take(960);
+        assert!(reused.is_empty());
+        assert_eq!(reused.as_ptr(), address);
+        assert!(pool.free.is_empty());
+    }
+
+    #[test]
+    fn wait_for_room_wakes_when_played() {
+        let buffer = Arc::new(SinkBuffer::for_current_thread());
+        let pool = SamplePool::default();
+        let _kept = stereo(&buffer, &pool, 480);
+        let played = stereo(&buffer, &pool, 480);
+        assert!(buffer.wait_for_room(960, Duration::from_secs(10)));
+
+        let output = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            played.for_each(drop);
+        });
+        assert!(buffer.wait_for_room(480, Duration::from_secs(10)));
+        assert_eq!(buffer.queued_frames(), 480);
+        output.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_room_gives_up_on_a_stalled_output() {
+        let buffer = Arc::new(SinkBuffer::for_current_thread());
+        let pool = SamplePool::default();
+        let _stalled = stereo(&buffer, &pool, 480);
+
+        let start = Instant::now();
+        assert!(!buffer.wait_for_room(0, Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(buffer.queued_frames(), 480);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tauri::State;
+
+pub const AUDIO_PIPELINE_STATS_EVENT: &str = "audio_pipeline_stats";
+
+/// How often the capture thread reports the stats.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters written by the sink and the capture thread. They are kept across
+/// restarts of the capture thread.
+#[derive(Default)]
+pub struct PipelineCounters {
+    chunks_produced: AtomicU64,
+    samples_produced: AtomicU64,
+    samples_dropped: AtomicU64,
+    chunks_emitted: AtomicU64,
+    chunks_dropped: AtomicU64,
+    sink_queue_buffers: AtomicUsize,
+}
+
+impl PipelineCounters {
+    /// Counts a packet the sink wrote to the capture ring.
+    pub fn produced(&self, samples: usize) {
+        self.chunks_produced.fetch_add(1, Ordering::Relaxed);
+        self.samples_produced
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    /// Counts samples overwritten before the capture thread read them.
+    pub fn samples_dropped(&self, samples: u64) {
+        self.samples_dropped.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    pub fn emitted(&self) {
+        self.chunks_emitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts feed chunks that were skipped or failed to send.
+    pub fn chunks_dropped(&self, chunks: u64) {
+        self.chunks_dropped.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    pub fn set_sink_queue(&self, buffers: usize) {
+        self.sink_queue_buffers.store(buffers, Ordering::Relaxed);
+    }
+}
+
+/// Time spent sending feed chunks to the webview since the last report.
+#[derive(Default)]
+pub struct EmitTimer {
+    total: Duration,
+    max: Duration,
+    count: u32,
+}
+
+impl EmitTimer {
+    pub fn record(&mut self, started: Instant) {
+        let elapsed = started.elapsed();
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        self.count += 1;
+    }
+
+    /// The average and the maximum in ms, starting a new window.
+    fn take(&mut self) -> (f64, f64) {
+        let average = match self.count {
+            0 => 0.0,
+            count => self.total.as_secs_f64() * 1000.0 / count as f64,
+        };
+        let max = self.max.as_secs_f64() * 1000.0;
+        *self = Self::default();
+        (average, max)
+    }
+}
+
+/// Health of the capture pipeline, payload of `audio_pipeline_stats`.
+/// Counts are totals since the Spotify instance started.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PipelineStats {
+    /// Packets the sink played and captured.
+    pub chunks_produced: u64,
+    pub samples_produced: u64,
+    /// Captured samples lost because the capture thread fell behind.
+    pub samples_dropped: u64,
+    /// Feed chunks sent to the webview.
+    pub chunks_emitted: u64,
+    /// Feed chunks skipped to catch up or that failed to send.
+    pub chunks_dropped: u64,
+    /// Time to send a feed chunk, over the last report interval.
+    pub emit_ms_avg: f64,
+    pub emit_ms_max: f64,
+    /// Audio queued in the output sink.
+    pub sink_queue_ms: f64,
+    pub sink_queue_buffers: usize,
+}
+
+impl PipelineStats {
+    pub fn collect(
+        counters: &PipelineCounters,
+        emits: &mut EmitTimer,
+        sink_queue: Duration,
+    ) -> Self {
+        let (emit_ms_avg, emit_ms_max) = emits.take();
+
+        Self {
+            chunks_produced: counters.chunks_produced.load(Ordering::Relaxed),
+            samples_produced: counters.samples_produced.load(Ordering::Relaxed),
+            samples_dropped: counters.samples_dropped.load(Ordering::Relaxed),
+            chunks_emitted: counters.chunks_emitted.load(Ordering::Relaxed),
+            chunks_dropped: counters.chunks_dropped.load(Ordering::Relaxed),
+            emit_ms_avg,
+            emit_ms_max,
+            sink_queue_ms: sink_queue.as_secs_f64() * 1000.0,
+            sink_queue_buffers: counters.sink_queue_buffers.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The latest report, managed by Tauri for `get_audio_pipeline_stats`.
+#[derive(Default)]
+pub struct PipelineStatsStore {
+    pub latest: Mutex<PipelineStats>,
+}
+
+#[tauri::command]
+pub fn get_audio_pipeline_stats(state: State<'_, PipelineStatsStore>) -> PipelineStats {
+    state.latest.lock().unwrap().clone()
+}
@@ -37,7 +37,6 @@ export const ButterchurnVisualizer = forwardRef<ButterchurnVisualizerHandle>(
 			playbackGainNodeRef: playbackGain,
 			isReady: isMounted,
 		} = useStreamedAudioVisualizer({
-			initialPreAmpGain: 6.0,
 			initialPlaybackGain: 2,
 		});
 
@@ -1,15 +1,78 @@
 import { useCallback, useEffect, useRef, useState, useMemo } from "react";
-import {
-  listen,
-  type Event as TauriEvent,
-  type UnlistenFn,
-} from "@tauri-apps/api/event";
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 
 // heavily vibe-coded (not really, but LLM's could find the issues in my initial code. I don't know anything about audio)
 
-const INCOMING_AUDIO_CHANNELS: 1 | 2 = 2; // 1 for mono, 2 for stereo
-const INCOMING_DATA_IS_FLOAT32: boolean = true; // true if numbers are -1.0 to 1.0, false if e.g., Int16
-export const DEFAULT_PRE_AMP_GAIN = 2.5; // Initial amplification factor (adjust as needed)
+// The feed is already normalized by the auto gain in the backend
+export const DEFAULT_PRE_AMP_GAIN = 1.0;
+
+type SampleFormat = "f32" | "i16";
+
+// Payload of the JSON audio_chunk event
+interface AudioChunkPayload {
+  sample_rate: number;
+  channels: number;
+  format: SampleFormat;
+  sample_index: number;
+  position_ms: number;
+  samples: number[];
+}
+
+interface AudioChunk {
+  sampleRate: number;
+  channels: number;
+  sampleIndex: number;
+  positionMs: number;
+  // Interleaved, -1.0 to 1.0
+  samples: Float32Array;
+}
+
+// Binary chunks start with sample rate (u32), channels (u16), format (u8,
+// 0 = f32, 1 = i16), a reserved byte, sample index (u64) and position in ms
+// (f64), followed by the samples, all little-endian
+const BINARY_HEADER_BYTES = 24;
+
+const decodeBinaryChunk = (data: ArrayBuffer): AudioChunk | null => {
+  if (data.byteLength < BINARY_HEADER_BYTES) {
+    return null;
+  }
+  const view = new DataView(data);
+  const format = view.getUint8(6);
+
+  let samples: Float32Array;
+  if (format === 0) {
+    samples = new Float32Array(data, BINARY_HEADER_BYTES);
+  } else if (format === 1) {
+    const ints = new Int16Array(data, BINARY_HEADER_BYTES);
+    samples = Float32Array.from(ints, (v) => v / 32767);
+  } else {
+    console.warn(`Unknown audio feed format ${format}. Skipping chunk.`);
+    return null;
+  }
+
+  return {
+    sampleRate: view.getUint32(0, true),
+    channels: view.getUint16(4, true),
+    sampleIndex: Number(view.getBigUint64(8, true)),
+    positionMs: view.getFloat64(16, true),
+    samples,
+  };
+};
+
+const fromPayload = (payload: AudioChunkPayload): AudioChunk | null => {
+  if (payload.format !== "f32") {
+    console.warn(`Unexpected audio_chunk format ${payload.format}.`);
+    return null;
+  }
+  return {
+    sampleRate: payload.sample_rate,
+    channels: payload.channels,
+    sampleIndex: payload.sample_index,
+    positionMs: payload.position_ms,
+    samples: new Float32Array(payload.samples),
+  };
+};
 
 interface UseStreamedAudioVisualizerOptions {
   initialPreAmpGain?: number;
@@ -115,13 +178,14 @@ export const useStreamedAudioVisualizer = (
     };
   }, [memoizedOptions]);
 
-  const handleAudioEvent = useCallback((event: TauriEvent<number[]>) => {
-    const chunk = event.payload;
+  // Index of the first frame not played yet, chunks may overlap
+  const nextSampleIndexRef = useRef<number | null>(null);
+
+  const playChunk = useCallback((chunk: AudioChunk | null) => {
     const context = audioContextRef.current;
     const preAmpGain = preAmpGainNodeRef.current;
 
-    if (!context || !preAmpGain || !chunk || chunk.length === 0) {
-      // console.warn("Context/Gain not ready or empty chunk in handleAudioEvent.");
+    if (!context || !preAmpGain || !chunk || chunk.samples.length === 0) {
       return;
     }
 
@@ -132,45 +196,41 @@ export const useStreamedAudioVisualizer = (
           .catch((e) => console.warn("Failed to resume audio context:", e));
       }
 
-      const sampleRate = context.sampleRate;
-      const frameCount = chunk.length / INCOMING_AUDIO_CHANNELS;
-
-      if (!Number.isInteger(frameCount) || frameCount <= 0) {
-        console.warn(`Invalid frame count (${frameCount}). Skipping chunk.`);
+      const { channels, samples } = chunk;
+      const totalFrames = samples.length / channels;
+      if (!Number.isInteger(totalFrames) || channels < 1 || channels > 2) {
+        console.warn(
+          `Invalid chunk (${samples.length} samples, ${channels} channels). Skipping chunk.`
+        );
         return;
       }
 
+      // Skip frames an earlier chunk already played. A lower index than
+      // before means the feed restarted.
+      let skip = 0;
+      const nextIndex = nextSampleIndexRef.current;
+      if (nextIndex !== null && chunk.sampleIndex < nextIndex) {
+        skip = nextIndex - chunk.sampleIndex;
+        if (skip >= totalFrames) {
+          if (chunk.sampleIndex + totalFrames < nextIndex - totalFrames) {
+            nextSampleIndexRef.current = null;
+          }
+          return;
+        }
+      }
+      nextSampleIndexRef.current = chunk.sampleIndex + totalFrames;
+      const frameCount = totalFrames - skip;
+
       const buffer = context.createBuffer(
-        INCOMING_AUDIO_CHANNELS,
+        channels,
         frameCount,
-        sampleRate
+        chunk.sampleRate
       );
-
-      let audioData: Float32Array;
-      if (INCOMING_DATA_IS_FLOAT32) {
-        audioData = new Float32Array(chunk);
-      } else {
-        console.error("Audio data conversion needed but not implemented.");
-        return;
-      }
-
-      if (INCOMING_AUDIO_CHANNELS === 2) {
-        if (audioData.length !== frameCount * 2) {
-          console.warn("Stereo data length mismatch. Skipping chunk.");
-          return;
-        }
-        const left = buffer.getChannelData(0);
-        const right = buffer.getChannelData(1);
+      for (let channel = 0; channel < channels; channel++) {
+        const data = buffer.getChannelData(channel);
         for (let i = 0; i < frameCount; i++) {
-          left[i] = audioData[i * 2];
-          right[i] = audioData[i * 2 + 1];
+          data[i] = samples[(skip + i) * channels + channel];
         }
-      } else {
-        if (audioData.length !== frameCount) {
-          console.warn("Mono data length mismatch. Skipping chunk.");
-          return;
-        }
-        buffer.getChannelData(0).set(audioData);
       }
 
       const source = context.createBufferSource();
@@ -189,40 +249,64 @@ export const useStreamedAudioVisualizer = (
   }, []);
 
   useEffect(() => {
-    if (isReady) {
-      const promise = listen<number[]>("audio_chunk", handleAudioEvent);
-
-      promise
-        .then((unlistenFn) => {
-          unlistenRef.current = unlistenFn;
-        })
-        .catch((err) => {
-          console.error(
-            "useStreamedAudioVisualizer: Failed to attach event listener:",
-            err
-          );
-          setError(`Failed to listen for audio_chunk: ${err}`);
-        });
-    } else {
-      if (unlistenRef.current) {
-        console.log(
-          "useStreamedAudioVisualizer: Audio not ready, detaching listener (if any)..."
-        );
-        unlistenRef.current();
-        unlistenRef.current = null;
-      }
+    if (!isReady) {
+      return;
     }
 
+    let cancelled = false;
+    let subscriptionId: number | null = null;
+
+    // Binary chunks over an IPC channel, the JSON
+    // audio_chunk event is only used if subscribing fails
+    const channel = new Channel<ArrayBuffer>();
+    channel.onmessage = (data) => playChunk(decodeBinaryChunk(data));
+
+    invoke<number>("subscribe_audio_feed", { channel, format: "f32" })
+      .then((id) => {
+        if (cancelled) {
+          invoke("unsubscribe_audio_feed", { id }).catch(() => {});
+        } else {
+          subscriptionId = id;
+        }
+      })
+      .catch((err) => {
+        console.warn(
+          "useStreamedAudioVisualizer: Binary audio feed unavailable, falling back to audio_chunk events:",
+          err
+        );
+        listen<AudioChunkPayload>("audio_chunk", (event) =>
+          playChunk(fromPayload(event.payload))
+        )
+          .then((unlistenFn) => {
+            if (cancelled) {
+              unlistenFn();
+            } else {
+              unlistenRef.current = unlistenFn;
+            }
+          })
+          .catch((err) => {
+            console.error(
+              "useStreamedAudioVisualizer: Failed to attach event listener:",
+              err
+            );
+            setError(`Failed to listen for audio_chunk: ${err}`);
+          });
+      });
+
     return () => {
-      if (unlistenRef.current) {
-        console.log(
-          "useStreamedAudioVisualizer: Cleaning up event listener..."
+      console.log("useStreamedAudioVisualizer: Cleaning up audio feed...");
+      cancelled = true;
+      if (subscriptionId !== null) {
+        invoke("unsubscribe_audio_feed", { id: subscriptionId }).catch(
+          (err) => console.warn("Failed to unsubscribe audio feed:", err)
         );
+      }
+      if (unlistenRef.current) {
         unlistenRef.current();
         unlistenRef.current = null;
       }
     };
-  }, [isReady, handleAudioEvent]);
+  }, [isReady, playChunk]);
 
   const resumeContext = useCallback(async (): Promise<void> => {
     if (